@@ -8,6 +8,7 @@ use std::{result::Result, sync::Arc};
 pub struct Database {
     pub scylla: Arc<ScyllaWrapper>,
     pub manticore: Arc<ManticoreWrapper>,
+    pub redis: Arc<RedisWrapper>,
     pub cache: Arc<CacheWrapper>,
 }
 
@@ -17,10 +18,12 @@ impl Database {
         let manticore = ManticoreWrapper::new(&config.manticore);
         let redis = RedisWrapper::new(&config.redis);
         let polls = futures::join!(scylla, manticore, redis);
+        let redis = Arc::new(polls.2?);
         Ok(Self {
             scylla: Arc::new(polls.0?),
             manticore: Arc::new(polls.1?),
-            cache: Arc::new(CacheWrapper::new(polls.2?)),
+            redis: redis.clone(),
+            cache: Arc::new(CacheWrapper::new(redis, &config.redis)),
         })
     }
 }
@@ -33,7 +36,7 @@ pub async fn sync(bundle: Arc<Database>) -> Result<(), DatabaseError> {
         bundle.clone(),
         bundle.clone().scylla.clone(),
     );
-    let manticore_synchronizers = super::manticore::schema::synchronizers();
+    let manticore_synchronizers = super::manticore::schema::synchronizers(&bundle.manticore);
     let manticore = super::sync::execute(
         manticore_synchronizers.clone().to_vec(),
         bundle.clone(),
@@ -43,5 +46,18 @@ pub async fn sync(bundle: Arc<Database>) -> Result<(), DatabaseError> {
     polls.0?;
     polls.1?;
     log::info!("Schema synchronization job completed");
+    check_scylla_schema(bundle).await
+}
+
+/// Report hand-made changes to the scylla keyspace,
+///     which the schema version alone can't tell.
+async fn check_scylla_schema(bundle: Arc<Database>) -> Result<(), DatabaseError> {
+    let diffs = bundle.scylla.schema_diff().await?;
+    for diff in diffs.iter() {
+        log::warn!("[scylla] live schema differ from expected: {:?}", diff);
+    }
+    if diffs.is_empty() {
+        log::info!("[scylla] live schema matches expected schema");
+    }
     Ok(())
 }
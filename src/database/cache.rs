@@ -1,16 +1,35 @@
 use super::{error::DatabaseError, redis::RedisWrapper};
-use r2d2_redis::redis::RedisError;
+use crate::server_config::redis::RedisConfig;
+use codec::Codec;
+use local::LocalCache;
+use redis::RedisError;
 use serde_cbor::Error as SerdeCborError;
-use std::{convert, sync::Arc};
+use std::{
+    collections::HashMap,
+    convert,
+    sync::{Arc, Mutex},
+};
+
+pub mod codec;
+pub mod load;
+pub mod local;
+pub mod tag;
 
 pub struct CacheWrapper {
-    redis: RedisWrapper,
+    redis: Arc<RedisWrapper>,
+    /// Keys being loaded by `get_or_load`, see `load.rs`.
+    inflight: Mutex<HashMap<String, Arc<tokio::sync::Mutex<()>>>>,
+    local: Option<LocalCache>,
+    /// Identify this replica in invalidation messages.
+    origin: String,
+    codec: Codec,
 }
 
 #[derive(Debug, Clone)]
 pub enum CacheError {
     RedisError(Arc<RedisError>),
     SerdeCborError(Arc<SerdeCborError>),
+    CodecError(String),
 }
 
 impl convert::From<RedisError> for CacheError {
@@ -25,51 +44,58 @@ impl convert::From<SerdeCborError> for CacheError {
     }
 }
 
-pub trait CacheModule {
-    fn set<K: Into<String>>(
-        &self,
-        key: K,
-        value: Vec<u8>,
-        expire: usize,
-    ) -> Result<(), DatabaseError>;
-    fn get<K: Into<String>>(&self, key: K) -> Result<Vec<u8>, DatabaseError>;
-}
-
 impl CacheWrapper {
-    pub fn new(redis: RedisWrapper) -> Self {
-        Self { redis }
+    pub fn new(redis: Arc<RedisWrapper>, config: &RedisConfig) -> Self {
+        let origin = format!("{}:{:?}", std::process::id(), std::time::SystemTime::now());
+        let local = LocalCache::new(config);
+        if let Some(local) = &local {
+            local.listen(redis.clone(), origin.clone());
+        }
+        Self {
+            redis,
+            inflight: Mutex::new(HashMap::new()),
+            local,
+            origin,
+            codec: Codec::new(config),
+        }
     }
 
     #[allow(dead_code)]
-    pub fn set<K, V>(&self, key: K, value: &V, expire: usize) -> Result<(), DatabaseError>
+    pub async fn set<K, V>(&self, key: K, value: &V, expire: usize) -> Result<(), DatabaseError>
     where
         K: std::convert::Into<String>,
         V: serde::ser::Serialize,
     {
-        let cbor = match serde_cbor::to_vec(&value) {
-            Err(err) => {
-                return Err(DatabaseError::CacheError(CacheError::SerdeCborError(
-                    Arc::new(err),
-                )))
-            }
-            Ok(cbor) => cbor,
-        };
-        self.redis.set(key, cbor, expire)?;
+        let encoded = self.codec.encode(value)?;
+        let key: String = key.into();
+        if let Some(local) = &self.local {
+            local.insert(key.clone(), encoded.clone());
+        }
+        self.redis.set(key.clone(), encoded, expire).await?;
+        // even without a local tier, other instances may have one.
+        self.publish_invalidation(&[key]).await;
         Ok(())
     }
 
     #[allow(dead_code)]
-    pub fn get<K, V>(&self, key: K) -> Result<V, DatabaseError>
+    pub async fn get<K, V>(&self, key: K) -> Result<Option<V>, DatabaseError>
     where
         K: std::convert::Into<String>,
         V: for<'a> serde::de::Deserialize<'a>,
     {
-        let cbor: Vec<u8> = self.redis.get(key)?;
-        match serde_cbor::de::from_slice::<V>(&cbor[..]) {
-            Err(err) => Err(DatabaseError::CacheError(CacheError::SerdeCborError(
-                Arc::new(err),
-            ))),
-            Ok(cbor) => Ok(cbor),
-        }
+        let key: String = key.into();
+        let encoded: Arc<Vec<u8>> = match self.local.as_ref().and_then(|local| local.get(&key)) {
+            Some(encoded) => encoded,
+            None => match self.redis.get(key.clone()).await? {
+                None => return Ok(None),
+                Some(encoded) => {
+                    if let Some(local) = &self.local {
+                        local.insert(key, encoded.clone());
+                    }
+                    Arc::new(encoded)
+                }
+            },
+        };
+        Ok(Some(self.codec.decode::<V>(&encoded[..])?))
     }
 }
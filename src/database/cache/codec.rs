@@ -0,0 +1,230 @@
+use super::CacheError;
+use crate::server_config::redis::{CacheCompression, CacheFormat, RedisConfig};
+use serde::{de::DeserializeOwned, Serialize};
+
+/// First byte of every value written with a header.
+/// `0xFF` is CBOR's "break" code, which can't start a value, so anything else
+///     is a bare CBOR value written before the header existed.
+const MAGIC: u8 = 0xFF;
+
+/// zstd's default level, a good tradeoff for small values.
+const ZSTD_LEVEL: i32 = 3;
+
+/// Serialize (and maybe compress) cached values.
+///
+/// Every value starts with `MAGIC` then a byte holding its format (low nibble)
+///     and compression (high nibble), so values written with other settings
+///     (e.g. by replicas not updated yet) can still be read.
+#[derive(Debug, Clone)]
+pub struct Codec {
+    format: CacheFormat,
+    compression: CacheCompression,
+    threshold: usize,
+}
+
+impl Codec {
+    pub fn new(config: &RedisConfig) -> Self {
+        Self {
+            format: config.cache_format,
+            compression: config.cache_compression,
+            threshold: config.cache_compression_threshold,
+        }
+    }
+
+    pub fn encode<V: Serialize>(&self, value: &V) -> Result<Vec<u8>, CacheError> {
+        let data = match self.format {
+            CacheFormat::Cbor => serde_cbor::to_vec(value)?,
+            CacheFormat::MessagePack => rmp_serde::to_vec_named(value)
+                .map_err(|err| CacheError::CodecError(err.to_string()))?,
+            CacheFormat::Bincode => {
+                bincode::serialize(value).map_err(|err| CacheError::CodecError(err.to_string()))?
+            }
+        };
+        let compression = if data.len() < self.threshold {
+            CacheCompression::None
+        } else {
+            self.compression
+        };
+        let data = match compression {
+            CacheCompression::None => data,
+            CacheCompression::Zstd => zstd::encode_all(&data[..], ZSTD_LEVEL)
+                .map_err(|err| CacheError::CodecError(err.to_string()))?,
+            CacheCompression::Lz4 => lz4_flex::compress_prepend_size(&data),
+        };
+        let mut value = Vec::with_capacity(data.len() + 2);
+        value.push(MAGIC);
+        value.push(header(self.format, compression));
+        value.extend_from_slice(&data);
+        Ok(value)
+    }
+
+    pub fn decode<V: DeserializeOwned>(&self, value: &[u8]) -> Result<V, CacheError> {
+        let (format, compression, data) = match value {
+            [MAGIC, header, data @ ..] => {
+                let (format, compression) = parse_header(*header)?;
+                (format, compression, data)
+            }
+            legacy => (CacheFormat::Cbor, CacheCompression::None, legacy),
+        };
+        let decompressed;
+        let data = match compression {
+            CacheCompression::None => data,
+            CacheCompression::Zstd => {
+                decompressed = zstd::decode_all(data)
+                    .map_err(|err| CacheError::CodecError(err.to_string()))?;
+                &decompressed[..]
+            }
+            CacheCompression::Lz4 => {
+                decompressed = lz4_flex::decompress_size_prepended(data)
+                    .map_err(|err| CacheError::CodecError(err.to_string()))?;
+                &decompressed[..]
+            }
+        };
+        Ok(match format {
+            CacheFormat::Cbor => serde_cbor::from_slice(data)?,
+            CacheFormat::MessagePack => rmp_serde::from_slice(data)
+                .map_err(|err| CacheError::CodecError(err.to_string()))?,
+            CacheFormat::Bincode => {
+                bincode::deserialize(data).map_err(|err| CacheError::CodecError(err.to_string()))?
+            }
+        })
+    }
+}
+
+fn header(format: CacheFormat, compression: CacheCompression) -> u8 {
+    let format = match format {
+        CacheFormat::Cbor => 0,
+        CacheFormat::MessagePack => 1,
+        CacheFormat::Bincode => 2,
+    };
+    let compression = match compression {
+        CacheCompression::None => 0,
+        CacheCompression::Zstd => 1,
+        CacheCompression::Lz4 => 2,
+    };
+    compression << 4 | format
+}
+
+fn parse_header(header: u8) -> Result<(CacheFormat, CacheCompression), CacheError> {
+    let format = match header & 0x0F {
+        0 => CacheFormat::Cbor,
+        1 => CacheFormat::MessagePack,
+        2 => CacheFormat::Bincode,
+        _ => {
+            return Err(CacheError::CodecError(format!(
+                "unknown header {:#x}",
+                header
+            )))
+        }
+    };
+    let compression = match header >> 4 {
+        0 => CacheCompression::None,
+        1 => CacheCompression::Zstd,
+        2 => CacheCompression::Lz4,
+        _ => {
+            return Err(CacheError::CodecError(format!(
+                "unknown header {:#x}",
+                header
+            )))
+        }
+    };
+    Ok((format, compression))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde::Deserialize;
+
+    #[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+    struct Entry {
+        id: u64,
+        name: String,
+        tags: Vec<String>,
+        cover: Option<String>,
+    }
+
+    const FORMATS: &[CacheFormat] = &[
+        CacheFormat::Cbor,
+        CacheFormat::MessagePack,
+        CacheFormat::Bincode,
+    ];
+
+    const COMPRESSIONS: &[CacheCompression] = &[
+        CacheCompression::None,
+        CacheCompression::Zstd,
+        CacheCompression::Lz4,
+    ];
+
+    fn codec(format: CacheFormat, compression: CacheCompression, threshold: usize) -> Codec {
+        Codec {
+            format,
+            compression,
+            threshold,
+        }
+    }
+
+    fn entries(count: usize) -> Vec<Entry> {
+        (0..count as u64)
+            .map(|id| Entry {
+                id,
+                name: format!("Thám tử lừng danh {}", id),
+                tags: vec!["mystery".to_owned(), "推理".to_owned()],
+                cover: if id % 2 == 0 {
+                    None
+                } else {
+                    Some("c.jpg".to_owned())
+                },
+            })
+            .collect()
+    }
+
+    #[test]
+    fn round_trip_every_setting() {
+        let value = entries(50);
+        for format in FORMATS.iter() {
+            for compression in COMPRESSIONS.iter() {
+                let codec = codec(*format, *compression, 0);
+                let encoded = codec.encode(&value).unwrap();
+                assert_eq!(encoded[0], MAGIC);
+                assert_eq!(codec.decode::<Vec<Entry>>(&encoded).unwrap(), value);
+            }
+        }
+    }
+
+    #[test]
+    fn read_values_written_with_other_settings() {
+        let value = entries(50);
+        let reader = codec(CacheFormat::Cbor, CacheCompression::None, 0);
+        for format in FORMATS.iter() {
+            for compression in COMPRESSIONS.iter() {
+                let encoded = codec(*format, *compression, 0).encode(&value).unwrap();
+                assert_eq!(reader.decode::<Vec<Entry>>(&encoded).unwrap(), value);
+            }
+        }
+    }
+
+    #[test]
+    fn read_legacy_cbor() {
+        let value = entries(3);
+        let legacy = serde_cbor::to_vec(&value).unwrap();
+        let codec = codec(CacheFormat::Bincode, CacheCompression::Zstd, 0);
+        assert_eq!(codec.decode::<Vec<Entry>>(&legacy).unwrap(), value);
+    }
+
+    #[test]
+    fn compress_only_above_threshold() {
+        let codec = codec(CacheFormat::MessagePack, CacheCompression::Zstd, 1024);
+        let small = codec.encode(&entries(1)).unwrap();
+        assert_eq!(small[1] >> 4, 0);
+        let large = codec.encode(&entries(100)).unwrap();
+        assert_eq!(large[1] >> 4, 1);
+    }
+
+    #[test]
+    fn reject_unknown_header() {
+        let codec = codec(CacheFormat::Cbor, CacheCompression::None, 0);
+        assert!(codec.decode::<u64>(&[MAGIC, 0x0F, 0]).is_err());
+        assert!(codec.decode::<u64>(&[MAGIC, 0xF0, 0]).is_err());
+    }
+}
@@ -0,0 +1,316 @@
+use super::CacheWrapper;
+use crate::database::error::DatabaseError;
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use std::{
+    future::Future,
+    sync::Arc,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+/// Prefix of the redis locks taken while loading a key.
+const LOCK_PREFIX: &str = "lock:";
+
+/// A loader holding the lock longer than this (in miliseconds) is considered dead.
+const LOCK_TTL: usize = 10 * 1000;
+
+/// How long other replicas wait for the lock holder to fill the cache
+///     before loading the value themselves.
+const LOCK_WAIT: Duration = Duration::from_secs(2);
+const LOCK_POLL: Duration = Duration::from_millis(50);
+
+/// TTLs are stretched by up to 1/10, so keys cached together don't all
+///     expire (and get reloaded) at the same time.
+const JITTER_RATIO: usize = 10;
+
+/// How long a loaded value is kept, in miliseconds.
+#[derive(Debug, Clone, Copy)]
+pub struct LoadOptions {
+    pub ttl: usize,
+    /// Once expired, the value is still served for this long while it's
+    ///     being reloaded in the background, 0 to always wait for the loader.
+    pub stale: usize,
+}
+
+#[derive(Serialize, Deserialize)]
+struct CacheEntry<V> {
+    value: V,
+    /// Unix time (in miliseconds) after which the value is stale.
+    fresh_until: u64,
+}
+
+enum Lookup<V> {
+    Fresh(V),
+    Stale(V),
+    Missing,
+}
+
+impl CacheWrapper {
+    /// Return the cached value of `key`, or run `loader` and cache its result.
+    ///
+    /// Concurrent misses on the same key only run the loader once: within the
+    ///     process they wait for each other, across replicas a redis lock is taken.
+    /// The cache failing never fails the call, the loader is used instead.
+    #[allow(dead_code)]
+    pub async fn get_or_load<V, F, Fut>(
+        self: &Arc<Self>,
+        key: &str,
+        options: LoadOptions,
+        loader: F,
+    ) -> Result<V, DatabaseError>
+    where
+        V: Serialize + DeserializeOwned + Send + Sync + 'static,
+        F: FnOnce() -> Fut + Send + 'static,
+        Fut: Future<Output = Result<V, DatabaseError>> + Send + 'static,
+    {
+        self.get_or_load_tagged(key, options, loader, |_| vec![])
+            .await
+    }
+
+    /// Same as `get_or_load`, the loaded value is also tagged with `tags(&value)`
+    ///     so it can be dropped with `invalidate_tag`.
+    pub async fn get_or_load_tagged<V, F, Fut, T>(
+        self: &Arc<Self>,
+        key: &str,
+        options: LoadOptions,
+        loader: F,
+        tags: T,
+    ) -> Result<V, DatabaseError>
+    where
+        V: Serialize + DeserializeOwned + Send + Sync + 'static,
+        F: FnOnce() -> Fut + Send + 'static,
+        Fut: Future<Output = Result<V, DatabaseError>> + Send + 'static,
+        T: Fn(&V) -> Vec<String> + Send + 'static,
+    {
+        match self.lookup::<V>(key).await {
+            Lookup::Fresh(value) => return Ok(value),
+            Lookup::Stale(value) => {
+                self.revalidate(key, options, loader, tags);
+                return Ok(value);
+            }
+            Lookup::Missing => {}
+        };
+
+        let flight = self.flight(key);
+        let _guard = flight.lock.lock().await;
+        // whoever we waited for has probably filled the cache.
+        match self.lookup::<V>(key).await {
+            Lookup::Fresh(value) | Lookup::Stale(value) => Ok(value),
+            Lookup::Missing => self.load(key, options, loader, tags).await,
+        }
+    }
+
+    async fn lookup<V: DeserializeOwned>(&self, key: &str) -> Lookup<V> {
+        match self.get::<_, CacheEntry<V>>(key).await {
+            Err(err) => {
+                log::warn!("[cache] failed to read {:?}: {:?}", key, err);
+                Lookup::Missing
+            }
+            Ok(None) => Lookup::Missing,
+            Ok(Some(entry)) if entry.fresh_until > now() => Lookup::Fresh(entry.value),
+            Ok(Some(entry)) => Lookup::Stale(entry.value),
+        }
+    }
+
+    async fn store<V: Serialize>(
+        &self,
+        key: &str,
+        value: &V,
+        options: LoadOptions,
+        tags: &[String],
+    ) {
+        let ttl = jitter(options.ttl, now());
+        let entry = CacheEntry {
+            value,
+            fresh_until: now() + ttl as u64,
+        };
+        // tagged first, so an invalidation can't miss a value that's been written.
+        let result = match self.tag(key, tags).await {
+            Ok(_) => self.set(key, &entry, ttl + options.stale).await,
+            err => err,
+        };
+        if let Err(err) = result {
+            log::warn!("[cache] failed to write {:?}: {:?}", key, err);
+        }
+    }
+
+    /// The process-wide lock of `key`, shared by every caller loading it.
+    fn flight(self: &Arc<Self>, key: &str) -> Flight {
+        let mut inflight = self.inflight.lock().unwrap_or_else(|err| err.into_inner());
+        Flight {
+            cache: self.clone(),
+            key: key.to_owned(),
+            lock: inflight.entry(key.to_owned()).or_default().clone(),
+        }
+    }
+
+    /// Run the loader while holding the redis lock of `key`, or wait for the
+    ///     replica holding it to fill the cache.
+    async fn load<V, F, Fut, T>(
+        &self,
+        key: &str,
+        options: LoadOptions,
+        loader: F,
+        tags: T,
+    ) -> Result<V, DatabaseError>
+    where
+        V: Serialize + DeserializeOwned,
+        F: FnOnce() -> Fut,
+        Fut: Future<Output = Result<V, DatabaseError>>,
+        T: Fn(&V) -> Vec<String>,
+    {
+        let lock = format!("{}{}", LOCK_PREFIX, key);
+        let token = token();
+        let locked = self
+            .redis
+            .try_lock(&lock, &token, LOCK_TTL)
+            .await
+            .unwrap_or_else(|err| {
+                log::warn!("[cache] failed to lock {:?}: {:?}", key, err);
+                true
+            });
+        if !locked {
+            let deadline = tokio::time::Instant::now() + LOCK_WAIT;
+            while tokio::time::Instant::now() < deadline {
+                tokio::time::sleep(LOCK_POLL).await;
+                match self.lookup::<V>(key).await {
+                    Lookup::Fresh(value) | Lookup::Stale(value) => return Ok(value),
+                    Lookup::Missing => {}
+                };
+            }
+        }
+        let result = loader().await;
+        if let Ok(value) = &result {
+            self.store(key, value, options, &tags(value)).await;
+        }
+        if locked {
+            if let Err(err) = self.redis.unlock(&lock, &token).await {
+                log::warn!("[cache] failed to unlock {:?}: {:?}", key, err);
+            }
+        }
+        result
+    }
+
+    /// Reload a stale value in the background, unless this process
+    ///     or another replica already is.
+    fn revalidate<V, F, Fut, T>(
+        self: &Arc<Self>,
+        key: &str,
+        options: LoadOptions,
+        loader: F,
+        tags: T,
+    ) where
+        V: Serialize + Send + Sync + 'static,
+        F: FnOnce() -> Fut + Send + 'static,
+        Fut: Future<Output = Result<V, DatabaseError>> + Send + 'static,
+        T: Fn(&V) -> Vec<String> + Send + 'static,
+    {
+        let flight = self.flight(key);
+        let claim = match flight.lock.clone().try_lock_owned() {
+            Err(_) => return,
+            Ok(guard) => Claim {
+                _guard: guard,
+                _flight: flight,
+            },
+        };
+        let cache = self.clone();
+        let key = key.to_owned();
+        tokio::spawn(async move {
+            let _claim = claim;
+            let lock = format!("{}{}", LOCK_PREFIX, key);
+            let token = token();
+            match cache.redis.try_lock(&lock, &token, LOCK_TTL).await {
+                Err(err) => {
+                    log::warn!("[cache] failed to lock {:?}: {:?}", key, err);
+                    return;
+                }
+                Ok(false) => return,
+                Ok(true) => {}
+            };
+            match loader().await {
+                Err(err) => log::warn!("[cache] failed to reload {:?}: {:?}", key, err),
+                Ok(value) => cache.store(&key, &value, options, &tags(&value)).await,
+            };
+            if let Err(err) = cache.redis.unlock(&lock, &token).await {
+                log::warn!("[cache] failed to unlock {:?}: {:?}", key, err);
+            }
+        });
+    }
+}
+
+/// A share of the process-wide lock of a key.
+/// The lock is forgotten once nobody uses it anymore, on drop so it's also
+///     the case when the caller is cancelled (e.g. the client disconnected).
+struct Flight {
+    cache: Arc<CacheWrapper>,
+    key: String,
+    lock: Arc<tokio::sync::Mutex<()>>,
+}
+
+impl Drop for Flight {
+    fn drop(&mut self) {
+        let mut inflight = self
+            .cache
+            .inflight
+            .lock()
+            .unwrap_or_else(|err| err.into_inner());
+        // only the map and us are left, no one else can get it while we hold the map.
+        let unused = Arc::strong_count(&self.lock) == 2;
+        if let Some(lock) = inflight.get(&self.key) {
+            if unused && Arc::ptr_eq(lock, &self.lock) {
+                inflight.remove(&self.key);
+            }
+        }
+    }
+}
+
+/// A flight whose lock is held by a background reload.
+/// Fields are dropped in order: the lock is released before the flight is.
+struct Claim {
+    _guard: tokio::sync::OwnedMutexGuard<()>,
+    _flight: Flight,
+}
+
+/// Unix time, in miliseconds.
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|now| now.as_millis() as u64)
+        .unwrap_or(0)
+}
+
+/// Identify the holder of a lock, unique enough across replicas.
+fn token() -> String {
+    let nanos = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|now| now.as_nanos())
+        .unwrap_or(0);
+    format!("{}:{}", std::process::id(), nanos)
+}
+
+/// Stretch `ttl` by a pseudo random amount (up to `1 / JITTER_RATIO`) taken from `seed`.
+fn jitter(ttl: usize, seed: u64) -> usize {
+    let max = ttl / JITTER_RATIO;
+    ttl + (seed % (max as u64 + 1)) as usize
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn jitter_stays_in_bounds() {
+        for seed in 0..1000 {
+            let ttl = jitter(60_000, seed * 7919);
+            assert!((60_000..=66_000).contains(&ttl), "ttl: {}", ttl);
+        }
+        assert_eq!(jitter(0, 42), 0);
+        assert_eq!(jitter(5, 42), 5);
+    }
+
+    #[test]
+    fn jitter_spreads_values() {
+        let ttls: std::collections::HashSet<usize> =
+            (0..100).map(|seed| jitter(60_000, seed * 104729)).collect();
+        assert!(ttls.len() > 50);
+    }
+}
@@ -0,0 +1,113 @@
+use super::{CacheError, CacheWrapper};
+use crate::database::{error::DatabaseError, redis::RedisWrapper};
+use crate::server_config::redis::RedisConfig;
+use futures::StreamExt;
+use moka::sync::Cache;
+use serde::{Deserialize, Serialize};
+use std::{sync::Arc, time::Duration};
+
+/// Channel every replica listens to, to evict keys changed elsewhere.
+const INVALIDATION_CHANNEL: &str = "cache:invalidate";
+
+/// Delay before subscribing again after losing the connection.
+const RESUBSCRIBE_DELAY: Duration = Duration::from_secs(1);
+
+/// Bounded in-memory copy of hot redis entries (still encoded), per replica.
+/// Evicted by frequency (TinyLFU) and after a short TTL, so a missed
+///     invalidation is never stale for long.
+#[derive(Clone)]
+pub struct LocalCache {
+    entries: Cache<String, Arc<Vec<u8>>>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+struct Invalidation {
+    /// Replica that sent it, which has already updated its own copy.
+    origin: String,
+    keys: Vec<String>,
+}
+
+impl LocalCache {
+    /// `None` if disabled in the config.
+    pub fn new(config: &RedisConfig) -> Option<Self> {
+        if config.local_capacity == 0 {
+            return None;
+        }
+        Some(Self {
+            entries: Cache::builder()
+                .max_capacity(config.local_capacity)
+                .time_to_live(Duration::from_millis(config.local_ttl))
+                .build(),
+        })
+    }
+
+    pub fn get(&self, key: &str) -> Option<Arc<Vec<u8>>> {
+        self.entries.get(key)
+    }
+
+    pub fn insert(&self, key: String, value: Vec<u8>) {
+        self.entries.insert(key, Arc::new(value));
+    }
+
+    pub fn remove(&self, keys: &[String]) {
+        for key in keys.iter() {
+            self.entries.invalidate(key);
+        }
+    }
+
+    /// Evict whatever other replicas change, in a background task.
+    /// Everything is dropped whenever the subscription is lost,
+    ///     as some messages may have been missed.
+    pub fn listen(&self, redis: Arc<RedisWrapper>, origin: String) {
+        let local = self.clone();
+        tokio::spawn(async move {
+            loop {
+                if let Err(err) = local.subscribe(&redis, &origin).await {
+                    log::warn!("[cache] lost the invalidation channel: {:?}", err);
+                }
+                local.entries.invalidate_all();
+                tokio::time::sleep(RESUBSCRIBE_DELAY).await;
+            }
+        });
+    }
+
+    async fn subscribe(&self, redis: &RedisWrapper, origin: &str) -> Result<(), DatabaseError> {
+        let mut pubsub = redis.pubsub().await?;
+        pubsub
+            .subscribe(INVALIDATION_CHANNEL)
+            .await
+            .map_err(CacheError::from)?;
+        let mut messages = pubsub.on_message();
+        while let Some(message) = messages.next().await {
+            let payload: Vec<u8> = message.get_payload().map_err(CacheError::from)?;
+            match serde_cbor::from_slice::<Invalidation>(&payload) {
+                Err(err) => log::warn!("[cache] malformed invalidation: {:?}", err),
+                Ok(invalidation) if invalidation.origin == origin => {}
+                Ok(invalidation) => self.remove(&invalidation.keys),
+            };
+        }
+        Err(DatabaseError::Other(
+            "the connection was closed".to_string(),
+        ))
+    }
+}
+
+impl CacheWrapper {
+    /// Tell the other replicas to drop their copy of `keys`.
+    pub(super) async fn publish_invalidation(&self, keys: &[String]) {
+        if keys.is_empty() {
+            return;
+        }
+        let invalidation = Invalidation {
+            origin: self.origin.clone(),
+            keys: keys.to_vec(),
+        };
+        let result = match serde_cbor::to_vec(&invalidation) {
+            Err(err) => Err(DatabaseError::from(CacheError::from(err))),
+            Ok(payload) => self.redis.publish(INVALIDATION_CHANNEL, payload).await,
+        };
+        if let Err(err) = result {
+            log::warn!("[cache] failed to publish an invalidation: {:?}", err);
+        }
+    }
+}
@@ -0,0 +1,96 @@
+use super::CacheWrapper;
+use crate::database::error::DatabaseError;
+
+/// Prefix of the redis sets holding the keys of each tag.
+const TAG_PREFIX: &str = "tag:";
+
+/// Prefix of the counters holding the version of each namespace.
+const NAMESPACE_PREFIX: &str = "namespace:";
+
+/// Tag sets outlive the keys they hold (in miliseconds),
+///     dangling members are harmless, deleting a missing key is a no-op.
+const TAG_TTL: usize = 24 * 60 * 60 * 1000;
+
+/// Number of keys deleted at once when invalidating a tag.
+const INVALIDATE_BATCH_SIZE: usize = 500;
+
+/// Namespace of the cached autocompletions,
+///     bumped when the whole index changes (e.g. after a rebuild).
+pub const SUGGEST_NAMESPACE: &str = "suggest";
+
+/// Tag of every cached value that depends on the titles as a whole
+///     (e.g. search results, which any title may join or leave).
+pub const TITLES_TAG: &str = "titles";
+
+/// Tag of every cached value that mentions a title.
+pub fn title_tag(id: u64) -> String {
+    format!("title:{}", id)
+}
+
+fn tag_key(tag: &str) -> String {
+    format!("{}{}", TAG_PREFIX, tag)
+}
+
+fn namespace_key(namespace: &str) -> String {
+    format!("{}{}", NAMESPACE_PREFIX, namespace)
+}
+
+fn versioned_key(namespace: &str, version: u64, key: &str) -> String {
+    format!("{}:v{}:{}", namespace, version, key)
+}
+
+impl CacheWrapper {
+    /// Remember that `key` mentions each of `tags`, see `invalidate_tag`.
+    pub async fn tag(&self, key: &str, tags: &[String]) -> Result<(), DatabaseError> {
+        let key = vec![key.to_owned()];
+        for tag in tags.iter() {
+            self.redis.add_to_set(&tag_key(tag), &key, TAG_TTL).await?;
+        }
+        Ok(())
+    }
+
+    /// Drop every cached key tagged with `tag`, return how many there were.
+    pub async fn invalidate_tag(&self, tag: &str) -> Result<usize, DatabaseError> {
+        let set = tag_key(tag);
+        let keys = self.redis.set_members(&set).await?;
+        for batch in keys.chunks(INVALIDATE_BATCH_SIZE) {
+            // untagged before being deleted: a key stored (and tagged) again in between
+            //     is either deleted below or back in the set, never left stale outside of it.
+            self.redis.remove_from_set(&set, batch).await?;
+            self.redis.delete(batch).await?;
+            if let Some(local) = &self.local {
+                local.remove(batch);
+            }
+            self.publish_invalidation(batch).await;
+        }
+        Ok(keys.len())
+    }
+
+    /// Prefix `key` with the current version of `namespace`, e.g. `suggest:v3:<key>`.
+    pub async fn namespaced(&self, namespace: &str, key: &str) -> Result<String, DatabaseError> {
+        let version = self.redis.counter(&namespace_key(namespace)).await?;
+        Ok(versioned_key(namespace, version, key))
+    }
+
+    /// Invalidate every key of `namespace` at once, without scanning for them:
+    ///     they're no longer reachable and will expire on their own.
+    pub async fn bump_namespace(&self, namespace: &str) -> Result<u64, DatabaseError> {
+        self.redis.increment(&namespace_key(namespace)).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn keys_layout() {
+        assert_eq!(tag_key(&title_tag(42)), "tag:title:42");
+        assert_eq!(namespace_key("suggest"), "namespace:suggest");
+        assert_eq!(versioned_key("suggest", 3, "10:oshi"), "suggest:v3:10:oshi");
+        assert_ne!(
+            versioned_key("suggest", 3, "10:oshi"),
+            versioned_key("suggest", 4, "10:oshi")
+        );
+    }
+}
@@ -1,7 +1,8 @@
 use super::cache::CacheError;
-use mysql::Error as MysqlError;
+use mysql::{DriverError, Error as MysqlError};
 use scylla::{
     cql_to_rust::FromRowError as ScyllaFromRowError,
+    frame::value::SerializeValuesError as ScyllaValuesError,
     transport::errors::{NewSessionError, QueryError as ScyllaQueryError},
 };
 use std::{convert, sync::Arc};
@@ -11,7 +12,21 @@ pub enum DatabaseError {
     ScyllaError(NewSessionError),
     ScyllaQueryError(ScyllaQueryError),
     ScyllaFromRowError(ScyllaFromRowError),
+    ScyllaValuesError(ScyllaValuesError),
+    /// A logged batch over the size limits (number of statements),
+    ///     splitting it would lose its atomicity.
+    ScyllaBatchTooLarge(usize),
+    /// The table doesn't exist (yet), e.g. before the first schema sync.
+    ManticoreMissingTable(String),
+    /// Manticore couldn't parse the query, this is a bug on our side.
+    ManticoreSyntaxError(String),
+    /// The connection dropped mid-way, the query may be retried with another one.
+    ManticoreConnectionLost(String),
+    /// An `INSERT` with an id that already exists.
+    ManticoreDuplicateId(String),
     MysqlError(Arc<MysqlError>),
+    /// Any other error returned by manticore's JSON api.
+    ManticoreHttpError(String),
     R2d2Error(String),
     CacheError(CacheError),
     Other(String),
@@ -35,9 +50,72 @@ impl convert::From<ScyllaFromRowError> for DatabaseError {
     }
 }
 
+impl convert::From<ScyllaValuesError> for DatabaseError {
+    fn from(err: ScyllaValuesError) -> Self {
+        DatabaseError::ScyllaValuesError(err)
+    }
+}
+
 impl convert::From<MysqlError> for DatabaseError {
     fn from(err: MysqlError) -> Self {
-        DatabaseError::MysqlError(Arc::new(err))
+        match &err {
+            MysqlError::MySqlError(server) => match classify_manticore_error(&server.message) {
+                Some(classify) => classify(server.message.clone()),
+                None => DatabaseError::MysqlError(Arc::new(err)),
+            },
+            // the client side of CR_SERVER_GONE_ERROR / CR_SERVER_LOST,
+            //     they never come back as a server error.
+            MysqlError::IoError(_)
+            | MysqlError::CodecError(_)
+            | MysqlError::DriverError(
+                DriverError::ConnectTimeout
+                | DriverError::CouldNotConnect(_)
+                | DriverError::PacketOutOfSync
+                | DriverError::Timeout,
+            ) => DatabaseError::ManticoreConnectionLost(err.to_string()),
+            _ => DatabaseError::MysqlError(Arc::new(err)),
+        }
+    }
+}
+
+/// Manticore answers (almost) every error with the same generic code,
+///     so the message is the only thing telling them apart.
+fn classify_manticore_error(message: &str) -> Option<fn(String) -> DatabaseError> {
+    let message = message.to_lowercase();
+    let contains = |patterns: &[&str]| patterns.iter().any(|pattern| message.contains(pattern));
+    if contains(&[
+        "unknown local table",
+        "no such table",
+        "unknown table",
+        "no such index",
+    ]) {
+        Some(DatabaseError::ManticoreMissingTable)
+    } else if contains(&["duplicate id"]) {
+        Some(DatabaseError::ManticoreDuplicateId)
+    } else if contains(&["syntax error", "parse error"]) {
+        Some(DatabaseError::ManticoreSyntaxError)
+    } else {
+        None
+    }
+}
+
+impl convert::From<reqwest::Error> for DatabaseError {
+    fn from(err: reqwest::Error) -> Self {
+        if err.is_connect() || err.is_timeout() {
+            return DatabaseError::ManticoreConnectionLost(err.to_string());
+        }
+        DatabaseError::ManticoreHttpError(err.to_string())
+    }
+}
+
+impl DatabaseError {
+    /// An error message returned by manticore's JSON api, classified the same
+    ///     way as the ones returned over SQL.
+    pub fn from_manticore_http(message: String) -> Self {
+        match classify_manticore_error(&message) {
+            Some(classify) => classify(message),
+            None => DatabaseError::ManticoreHttpError(message),
+        }
     }
 }
 
@@ -52,3 +130,57 @@ impl convert::From<CacheError> for DatabaseError {
         DatabaseError::CacheError(err)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use mysql::MySqlError;
+    use std::io;
+
+    fn classify(message: &str) -> Option<DatabaseError> {
+        classify_manticore_error(message).map(|classify| classify(message.to_owned()))
+    }
+
+    #[test]
+    fn classify_manticore_messages() {
+        assert!(matches!(
+            classify("unknown local table(s) 'sync_data' in search request"),
+            Some(DatabaseError::ManticoreMissingTable(_))
+        ));
+        assert!(matches!(
+            classify("no such table 'titles'"),
+            Some(DatabaseError::ManticoreMissingTable(_))
+        ));
+        assert!(matches!(
+            classify("P01: syntax error, unexpected identifier near 'SELEC'"),
+            Some(DatabaseError::ManticoreSyntaxError(_))
+        ));
+        assert!(matches!(
+            classify("duplicate id '42'"),
+            Some(DatabaseError::ManticoreDuplicateId(_))
+        ));
+        assert!(classify("table titles: out of memory").is_none());
+    }
+
+    #[test]
+    fn classify_lost_connections() {
+        let reset = MysqlError::IoError(io::Error::from(io::ErrorKind::ConnectionReset));
+        assert!(matches!(
+            DatabaseError::from(reset),
+            DatabaseError::ManticoreConnectionLost(_)
+        ));
+        assert!(matches!(
+            DatabaseError::from(MysqlError::DriverError(DriverError::ConnectTimeout)),
+            DatabaseError::ManticoreConnectionLost(_)
+        ));
+        let server = MysqlError::MySqlError(MySqlError {
+            state: "HY000".to_owned(),
+            message: "no such table 'titles'".to_owned(),
+            code: 1064,
+        });
+        assert!(matches!(
+            DatabaseError::from(server),
+            DatabaseError::ManticoreMissingTable(_)
+        ));
+    }
+}
@@ -0,0 +1,478 @@
+use super::{
+    bundle::Database,
+    cache::{
+        tag::{title_tag, SUGGEST_NAMESPACE, TITLES_TAG},
+        CacheError,
+    },
+    error::DatabaseError,
+    jobs::{self, Job},
+    manticore::search::TitleDocument,
+    scylla::ScyllaWrapper,
+};
+use scylla::{macros::FromRow, IntoTypedRows};
+use serde::{Deserialize, Serialize};
+use std::{collections::HashMap, sync::Arc, time::Duration};
+
+/// Redis list holding index writes that failed, to be retried later.
+/// Both queues share a hash tag, so they sit on the same node of a cluster.
+const RETRY_QUEUE: &str = "{indexer}:retry";
+
+/// Redis list holding the tasks that are being retried right now.
+const PROCESSING_QUEUE: &str = "{indexer}:processing";
+
+/// How often the retry queue is drained.
+const RETRY_INTERVAL: Duration = Duration::from_secs(10);
+
+/// How often the whole index is compared against scylla.
+const RECONCILE_INTERVAL: Duration = Duration::from_secs(60 * 60);
+
+/// Number of titles checked at once during a reconciliation.
+const RECONCILE_PAGE_SIZE: i32 = 500;
+
+/// Scylla refuses a `WHERE id IN ?` with more partition keys than this
+///     (`max_partition_key_restrictions_per_query`).
+const MAX_PARTITION_KEYS: usize = 100;
+
+/// Only one instance reconciles the index per `RECONCILE_INTERVAL`.
+/// It's never released, but expires a bit before the next round,
+///     otherwise every instance would still run one in turn.
+const RECONCILE_LOCK: &str = "indexer:reconcile";
+
+/// Expiration of `RECONCILE_LOCK` (in miliseconds).
+const RECONCILE_LOCK_TTL: usize = 55 * 60 * 1000;
+
+/// Only one instance rebuilds the index at a time.
+const REBUILD_LOCK: &str = "indexer:rebuild";
+
+/// A rebuild taking longer than this (in miliseconds) is considered dead.
+const REBUILD_LOCK_TTL: usize = 6 * 60 * 60 * 1000;
+
+/// How long the previous table is kept after a rebuild (a few `RETRY_INTERVAL`),
+///     so instances that haven't noticed the switch yet can still search it.
+const REBUILD_GRACE_PERIOD: Duration = Duration::from_secs(30);
+
+/// A pending write to the search index.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub enum IndexTask {
+    /// Make the title's document match scylla (deleting it if the title is gone).
+    Title(u64),
+}
+
+/// Job rebuilding the titles table, see `Indexer::rebuild`.
+/// Queued at startup when the titles settings changed, so it's retried
+///     and survives the instance going away.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct RebuildTitles;
+
+impl Job for RebuildTitles {
+    const KIND: &'static str = "rebuild_titles";
+}
+
+/// What a reconciliation had to fix.
+#[derive(Debug, Clone, Default)]
+pub struct ReconcileReport {
+    pub checked: usize,
+    pub missing: usize,
+    pub stale: usize,
+    pub orphaned: usize,
+}
+
+#[derive(FromRow)]
+struct TitleRow {
+    id: i64,
+    name: Option<String>,
+    alt_names: Option<Vec<String>>,
+    authors: Option<Vec<String>>,
+    tags: Option<Vec<String>>,
+    publisher: Option<String>,
+    format: Option<String>,
+    status: Option<String>,
+    cover: Option<String>,
+    updated_at: Option<i64>,
+}
+
+const TITLE_COLUMNS: &str =
+    "id, name, alt_names, authors, tags, publisher, format, status, cover, updated_at";
+
+impl From<TitleRow> for TitleDocument {
+    fn from(row: TitleRow) -> Self {
+        Self {
+            id: row.id as u64,
+            name: row.name.unwrap_or_default(),
+            alt_names: row.alt_names.unwrap_or_default(),
+            authors: row.authors.unwrap_or_default(),
+            tags: row.tags.unwrap_or_default(),
+            publisher: row.publisher.unwrap_or_default(),
+            format: row.format.unwrap_or_default(),
+            status: row.status.unwrap_or_default(),
+            cover: row.cover.unwrap_or_default(),
+            updated_at: row.updated_at.unwrap_or_default(),
+        }
+    }
+}
+
+/// Keep manticore's search index in sync with scylla, which is the source of truth.
+pub struct Indexer {
+    database: Arc<Database>,
+}
+
+impl Indexer {
+    pub fn new(database: Arc<Database>) -> Self {
+        Self { database }
+    }
+
+    /// Call this once a mutation of a title (or one of its volumes) has been committed.
+    /// A failed index write is queued for retry instead of failing the mutation.
+    // no mutation writes titles yet.
+    #[allow(dead_code)]
+    pub async fn title_committed(&self, id: u64) -> Result<(), DatabaseError> {
+        let result = self.sync_title(id).await;
+        // only once the index is written, or a concurrent search could cache
+        //     the old document again right away.
+        self.invalidate_title(id).await;
+        if let Err(err) = result {
+            log::warn!("[indexer] failed to index title #{}: {:?}", id, err);
+            self.enqueue(&IndexTask::Title(id)).await?;
+        }
+        Ok(())
+    }
+
+    async fn invalidate_title(&self, id: u64) {
+        for tag in [title_tag(id), TITLES_TAG.to_string()].iter() {
+            if let Err(err) = self.database.cache.invalidate_tag(tag).await {
+                log::warn!("[indexer] failed to invalidate title #{}: {:?}", id, err);
+            }
+        }
+    }
+
+    async fn sync_title(&self, id: u64) -> Result<(), DatabaseError> {
+        match title_document(&self.database.scylla, id).await? {
+            None => {
+                self.database
+                    .manticore
+                    .blocking(move |manticore| manticore.delete_titles(&[id]))
+                    .await
+            }
+            Some(document) => self.database.manticore.index_titles(vec![document]).await,
+        }
+    }
+
+    async fn execute(&self, task: &IndexTask) -> Result<(), DatabaseError> {
+        match task {
+            IndexTask::Title(id) => {
+                self.sync_title(*id).await?;
+                self.invalidate_title(*id).await;
+                Ok(())
+            }
+        }
+    }
+
+    async fn enqueue(&self, task: &IndexTask) -> Result<(), DatabaseError> {
+        let cbor = serde_cbor::to_vec(task).map_err(CacheError::from)?;
+        self.database.redis.push(RETRY_QUEUE, cbor).await
+    }
+
+    /// Retry queued tasks until the queue is empty or one of them fails again,
+    ///     return how many have been done.
+    pub async fn drain_retry_queue(&self) -> Result<usize, DatabaseError> {
+        let redis = self.database.redis.clone();
+        let mut done = 0;
+        while let Some(cbor) = redis.claim(RETRY_QUEUE, PROCESSING_QUEUE).await? {
+            let task = match serde_cbor::from_slice::<IndexTask>(&cbor) {
+                Err(err) => {
+                    log::error!("[indexer] dropping malformed task: {:?}", err);
+                    redis.ack(PROCESSING_QUEUE, &cbor).await?;
+                    continue;
+                }
+                Ok(task) => task,
+            };
+            if let Err(err) = self.execute(&task).await {
+                // still failing, leave the rest for the next round.
+                redis.push(RETRY_QUEUE, cbor.clone()).await?;
+                redis.ack(PROCESSING_QUEUE, &cbor).await?;
+                return Err(err);
+            }
+            redis.ack(PROCESSING_QUEUE, &cbor).await?;
+            done += 1;
+        }
+        Ok(done)
+    }
+
+    /// Scan every title in scylla and fix missing or stale documents,
+    ///     then remove the documents of titles that no longer exist.
+    pub async fn reconcile(&self) -> Result<ReconcileReport, DatabaseError> {
+        let scylla = self.database.scylla.clone();
+        let manticore = self.database.manticore.clone();
+        let mut report = ReconcileReport::default();
+
+        let query = format!("SELECT {} FROM titles", TITLE_COLUMNS);
+        let mut paging_state = None;
+        loop {
+            let page = scylla
+                .query_page::<TitleRow>(&query, &[], RECONCILE_PAGE_SIZE, paging_state)
+                .await?;
+            let documents: Vec<TitleDocument> =
+                page.rows.into_iter().map(TitleDocument::from).collect();
+            let ids: Vec<u64> = documents.iter().map(|document| document.id).collect();
+            let versions: HashMap<u64, i64> = manticore
+                .blocking(move |manticore| manticore.title_versions(&ids))
+                .await?
+                .into_iter()
+                .collect();
+            let mut outdated = vec![];
+            for document in documents.into_iter() {
+                report.checked += 1;
+                match versions.get(&document.id) {
+                    Some(updated_at) if *updated_at == document.updated_at => continue,
+                    Some(_) => report.stale += 1,
+                    None => report.missing += 1,
+                };
+                outdated.push(document);
+            }
+            manticore.index_titles(outdated).await?;
+            if page.paging_state.is_none() {
+                break;
+            }
+            paging_state = page.paging_state;
+        }
+
+        let mut after = 0;
+        loop {
+            let ids = manticore
+                .blocking(move |manticore| {
+                    manticore.title_ids_after(after, RECONCILE_PAGE_SIZE as u32)
+                })
+                .await?;
+            let last = match ids.last() {
+                None => break,
+                Some(last) => *last,
+            };
+            let existing = existing_titles(&scylla, &ids).await?;
+            let orphans: Vec<u64> = ids
+                .into_iter()
+                .filter(|id| !existing.contains(id))
+                .collect();
+            report.orphaned += orphans.len();
+            manticore
+                .blocking(move |manticore| manticore.delete_titles(&orphans))
+                .await?;
+            after = last;
+        }
+        Ok(report)
+    }
+
+    /// `reconcile`, unless another instance already did it this round.
+    /// Return `None` when skipped.
+    pub async fn reconcile_once(&self) -> Result<Option<ReconcileReport>, DatabaseError> {
+        if !self
+            .database
+            .redis
+            .try_lock(RECONCILE_LOCK, &lock_token(), RECONCILE_LOCK_TTL)
+            .await?
+        {
+            return Ok(None);
+        }
+        self.reconcile().await.map(Some)
+    }
+
+    /// Whether the titles table was created with other settings than the current ones.
+    pub async fn titles_settings_changed(&self) -> Result<bool, DatabaseError> {
+        self.database
+            .manticore
+            .blocking(|manticore| manticore.titles_settings_changed())
+            .await
+    }
+
+    /// Build a new titles table with the current settings, fill it from scylla,
+    ///     then switch searches to it. The old table keeps serving (and
+    ///     receiving writes) until then.
+    /// Return `false` if another instance is already rebuilding.
+    pub async fn rebuild(&self) -> Result<bool, DatabaseError> {
+        let redis = self.database.redis.clone();
+        let token = lock_token();
+        if !redis
+            .try_lock(REBUILD_LOCK, &token, REBUILD_LOCK_TTL)
+            .await?
+        {
+            return Ok(false);
+        }
+        let result = self.rebuild_locked().await;
+        if let Err(err) = redis.unlock(REBUILD_LOCK, &token).await {
+            log::warn!("[indexer] failed to release the rebuild lock: {:?}", err);
+        }
+        result.map(|_| true)
+    }
+
+    async fn rebuild_locked(&self) -> Result<(), DatabaseError> {
+        let scylla = self.database.scylla.clone();
+        let manticore = self.database.manticore.clone();
+        let table = manticore
+            .blocking(|manticore| manticore.start_titles_rebuild())
+            .await?;
+        log::info!("[indexer] rebuilding titles into {}", table);
+
+        let query = format!("SELECT {} FROM titles", TITLE_COLUMNS);
+        let mut count = 0;
+        let mut paging_state = None;
+        loop {
+            let page = scylla
+                .query_page::<TitleRow>(&query, &[], RECONCILE_PAGE_SIZE, paging_state)
+                .await?;
+            let documents: Vec<TitleDocument> =
+                page.rows.into_iter().map(TitleDocument::from).collect();
+            count += documents.len();
+            manticore
+                .index_titles_into(table.clone(), documents)
+                .await?;
+            if page.paging_state.is_none() {
+                break;
+            }
+            paging_state = page.paging_state;
+        }
+
+        let old = manticore
+            .blocking(|manticore| manticore.finish_titles_rebuild())
+            .await?;
+        log::info!("[indexer] {} now serves searches ({} titles)", table, count);
+        if let Err(err) = self.database.cache.bump_namespace(SUGGEST_NAMESPACE).await {
+            log::warn!(
+                "[indexer] failed to invalidate cached suggestions: {:?}",
+                err
+            );
+        }
+        if let Err(err) = self.database.cache.invalidate_tag(TITLES_TAG).await {
+            log::warn!("[indexer] failed to invalidate cached responses: {:?}", err);
+        }
+        // writes that raced with the backfill may have been overwritten by older rows.
+        let report = self.reconcile().await?;
+        log::info!("[indexer] post-rebuild reconciliation done: {:?}", report);
+        tokio::time::sleep(REBUILD_GRACE_PERIOD).await;
+        manticore
+            .blocking(move |manticore| manticore.drop_table(&old))
+            .await
+    }
+}
+
+/// Identify the holder of a lock.
+fn lock_token() -> String {
+    format!("{}:{:?}", std::process::id(), std::time::SystemTime::now())
+}
+
+async fn title_document(
+    scylla: &ScyllaWrapper,
+    id: u64,
+) -> Result<Option<TitleDocument>, DatabaseError> {
+    let query = ScyllaWrapper::idempotent(&format!(
+        "SELECT {} FROM titles WHERE id = ?",
+        TITLE_COLUMNS
+    ));
+    let result = scylla
+        .retry
+        .run(|| scylla.session.query(query.clone(), (id as i64,)))
+        .await?;
+    match result
+        .rows
+        .unwrap_or_default()
+        .into_typed::<TitleRow>()
+        .next()
+    {
+        None => Ok(None),
+        Some(row) => Ok(Some(TitleDocument::from(row?))),
+    }
+}
+
+async fn existing_titles(scylla: &ScyllaWrapper, ids: &[u64]) -> Result<Vec<u64>, DatabaseError> {
+    let query = ScyllaWrapper::idempotent("SELECT id FROM titles WHERE id IN ?");
+    let lookups = partition_keys(ids).into_iter().map(|keys| {
+        let query = query.clone();
+        async move {
+            scylla
+                .retry
+                .run(|| scylla.session.query(query.clone(), (&keys,)))
+                .await
+        }
+    });
+    let mut existing = vec![];
+    for result in futures::future::try_join_all(lookups).await? {
+        for row in result.rows.unwrap_or_default().into_typed::<(i64,)>() {
+            existing.push(row?.0 as u64);
+        }
+    }
+    Ok(existing)
+}
+
+/// Split ids into lists small enough for a single `IN`.
+fn partition_keys(ids: &[u64]) -> Vec<Vec<i64>> {
+    ids.chunks(MAX_PARTITION_KEYS)
+        .map(|chunk| chunk.iter().map(|id| *id as i64).collect())
+        .collect()
+}
+
+/// Background loop, retry failed index writes and reconcile the whole index periodically.
+pub async fn run(database: Arc<Database>) {
+    let indexer = Indexer::new(database);
+    if let Err(err) = indexer
+        .database
+        .redis
+        .requeue(PROCESSING_QUEUE, RETRY_QUEUE)
+        .await
+    {
+        log::error!("[indexer] failed to recover interrupted tasks: {:?}", err);
+    }
+    match indexer.titles_settings_changed().await {
+        Err(err) => log::error!("[indexer] failed to check the titles settings: {:?}", err),
+        Ok(false) => {}
+        // run by a job worker, retries and reconciliation go on in the meantime.
+        Ok(true) => match jobs::enqueue(&indexer.database.redis, &RebuildTitles).await {
+            Err(err) => log::error!("[indexer] failed to queue the titles rebuild: {:?}", err),
+            Ok(id) => log::info!(
+                "[indexer] titles settings changed, rebuild queued as {}",
+                id
+            ),
+        },
+    }
+    let manticore = indexer.database.manticore.clone();
+    let mut retry = tokio::time::interval(RETRY_INTERVAL);
+    let mut reconcile = tokio::time::interval(RECONCILE_INTERVAL);
+    loop {
+        tokio::select! {
+            _ = retry.tick() => {
+                // another instance may have started or finished a rebuild.
+                if let Err(err) = manticore
+                    .blocking(|manticore| manticore.refresh_titles_tables())
+                    .await
+                {
+                    log::warn!("[indexer] failed to refresh the titles tables: {:?}", err);
+                }
+                match indexer.drain_retry_queue().await {
+                    Err(err) => log::warn!("[indexer] retry queue stalled: {:?}", err),
+                    Ok(0) => {}
+                    Ok(done) => log::info!("[indexer] retried {} index task(s)", done),
+                }
+            },
+            _ = reconcile.tick() => match indexer.reconcile_once().await {
+                Err(err) => log::error!("[indexer] reconciliation failed: {:?}", err),
+                Ok(None) => log::debug!("[indexer] reconciled by another instance"),
+                Ok(Some(report)) => log::info!("[indexer] reconciliation done: {:?}", report),
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn partition_keys_fit_in_a_query() {
+        assert!(partition_keys(&[]).is_empty());
+
+        let ids: Vec<u64> = (1..=250).collect();
+        let keys = partition_keys(&ids);
+        let sizes: Vec<usize> = keys.iter().map(|keys| keys.len()).collect();
+        assert_eq!(sizes, vec![100, 100, 50]);
+        assert_eq!(keys.concat(), (1..=250).collect::<Vec<i64>>());
+
+        assert_eq!(partition_keys(&ids[..100]).len(), 1);
+    }
+}
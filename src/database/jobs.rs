@@ -0,0 +1,503 @@
+use super::{
+    bundle::Database,
+    cache::CacheError,
+    error::DatabaseError,
+    indexer::{Indexer, RebuildTitles},
+    redis::RedisWrapper,
+};
+use futures::future::BoxFuture;
+use redis::{streams::StreamId, FromRedisValue};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use std::{
+    collections::HashMap,
+    future::Future,
+    sync::Arc,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+/// Redis stream of the jobs waiting to run, or running until acknowledged.
+/// Every key shares a hash tag, so they sit on the same node of a cluster.
+const JOBS_STREAM: &str = "{jobs}:stream";
+
+/// Failed jobs waiting for their next attempt, scored by when it's due.
+const DELAYED_JOBS: &str = "{jobs}:delayed";
+
+/// Jobs that failed every attempt, kept (with their last error) for inspection.
+const DEAD_JOBS: &str = "{jobs}:dead";
+
+/// Consumer group shared by the workers of every instance.
+const GROUP: &str = "workers";
+
+/// Field of the stream entries holding the job.
+const JOB_FIELD: &str = "job";
+
+/// Streams are trimmed to about this many entries.
+const MAX_STREAM_LEN: usize = 100_000;
+
+/// A job is given up (and dead-lettered) after failing this many times.
+const MAX_ATTEMPTS: u32 = 5;
+
+/// Delay before retrying a failed job, doubled at every attempt (in miliseconds).
+const BACKOFF_BASE: u64 = 5 * 1000;
+const BACKOFF_MAX: u64 = 30 * 60 * 1000;
+
+/// A job pending for longer than this (in miliseconds) has been abandoned,
+///     by a worker that crashed or was stopped.
+const VISIBILITY_TIMEOUT: usize = 10 * 60 * 1000;
+
+/// How often a running job is marked as still alive, well within `VISIBILITY_TIMEOUT`.
+const HEARTBEAT_INTERVAL: Duration = Duration::from_secs(60);
+
+/// A job abandoned this many times probably takes its worker down with it.
+const MAX_ABANDONED: u32 = 3;
+
+/// How often idle workers look for new jobs.
+const POLL_INTERVAL: Duration = Duration::from_millis(500);
+
+/// Number of jobs recovered (or released) at once.
+const BATCH_SIZE: usize = 10;
+
+/// How often the queue is logged, when it's not empty.
+const STATS_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Work to run in the background, out of the request path.
+pub trait Job: Serialize + DeserializeOwned + Send + 'static {
+    /// Name of the job in the queue, it must not change while some are queued.
+    const KIND: &'static str;
+}
+
+/// What's stored in the stream, the job itself is kept as a CBOR value
+///     so workers can read the envelope without knowing its type.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+struct Envelope {
+    /// Unique enough to tell apart identical jobs in `DELAYED_JOBS`.
+    id: String,
+    kind: String,
+    job: serde_cbor::Value,
+    /// Number of failed attempts so far.
+    attempt: u32,
+    /// Number of times a worker stopped while running it.
+    #[serde(default)]
+    abandoned: u32,
+    error: Option<String>,
+}
+
+impl Envelope {
+    fn encode(&self) -> Result<Vec<u8>, DatabaseError> {
+        Ok(serde_cbor::to_vec(self).map_err(CacheError::from)?)
+    }
+
+    fn decode(entry: &StreamId) -> Result<Self, DatabaseError> {
+        let value = entry
+            .map
+            .get(JOB_FIELD)
+            .ok_or_else(|| DatabaseError::Other(format!("{} has no job", entry.id)))?;
+        let cbor = Vec::<u8>::from_redis_value(value).map_err(CacheError::from)?;
+        Ok(serde_cbor::from_slice(&cbor).map_err(CacheError::from)?)
+    }
+}
+
+/// A job sitting in the queue.
+#[derive(Debug, Clone)]
+pub struct PendingJob {
+    pub id: String,
+    pub consumer: String,
+    /// Since it was last delivered, in miliseconds.
+    pub idle: usize,
+    pub deliveries: usize,
+}
+
+/// Where the jobs are at.
+#[derive(Debug, Clone, Default)]
+pub struct QueueStats {
+    /// Waiting for a worker.
+    pub queued: u64,
+    /// Delivered to a worker, not done yet.
+    pub running: u64,
+    /// Waiting for their next attempt.
+    pub delayed: u64,
+    pub dead: u64,
+    /// Number of running jobs, per consumer.
+    pub consumers: Vec<(String, u64)>,
+}
+
+type Handler = Box<
+    dyn Fn(Arc<Database>, serde_cbor::Value) -> BoxFuture<'static, Result<(), DatabaseError>>
+        + Send
+        + Sync,
+>;
+
+/// Queue `job`, return its id in the stream.
+pub async fn enqueue<J: Job>(redis: &RedisWrapper, job: &J) -> Result<String, DatabaseError> {
+    let envelope = Envelope {
+        id: token(),
+        kind: J::KIND.to_string(),
+        job: serde_cbor::value::to_value(job).map_err(CacheError::from)?,
+        attempt: 0,
+        abandoned: 0,
+        error: None,
+    };
+    redis
+        .stream_add(JOBS_STREAM, MAX_STREAM_LEN, JOB_FIELD, envelope.encode()?)
+        .await
+}
+
+/// Run queued jobs with the handler registered for their kind.
+pub struct Jobs {
+    database: Arc<Database>,
+    handlers: HashMap<&'static str, Handler>,
+    /// Prefix of this instance's consumer names.
+    instance: String,
+}
+
+impl Jobs {
+    pub fn new(database: Arc<Database>) -> Self {
+        Self {
+            database,
+            handlers: HashMap::new(),
+            instance: token(),
+        }
+    }
+
+    pub fn register<J, F, Fut>(mut self, handler: F) -> Self
+    where
+        J: Job,
+        F: Fn(Arc<Database>, J) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = Result<(), DatabaseError>> + Send + 'static,
+    {
+        let handler: Handler = Box::new(move |database, job| -> BoxFuture<'static, _> {
+            match serde_cbor::value::from_value::<J>(job) {
+                Err(err) => Box::pin(futures::future::ready(Err(CacheError::from(err).into()))),
+                Ok(job) => Box::pin(handler(database, job)),
+            }
+        });
+        self.handlers.insert(J::KIND, handler);
+        self
+    }
+
+    /// Jobs delivered but not done yet, idle for at least `min_idle` miliseconds,
+    ///     oldest first from `start` (see `RedisWrapper::stream_pending`).
+    pub async fn pending(
+        &self,
+        min_idle: usize,
+        start: &str,
+        count: usize,
+    ) -> Result<Vec<PendingJob>, DatabaseError> {
+        let reply = self
+            .database
+            .redis
+            .stream_pending(JOBS_STREAM, GROUP, min_idle, start, count)
+            .await?;
+        Ok(reply
+            .ids
+            .into_iter()
+            .map(|pending| PendingJob {
+                id: pending.id,
+                consumer: pending.consumer,
+                idle: pending.last_delivered_ms,
+                deliveries: pending.times_delivered,
+            })
+            .collect())
+    }
+
+    pub async fn stats(&self) -> Result<QueueStats, DatabaseError> {
+        let redis = &self.database.redis;
+        let mut stats = QueueStats::default();
+        if let redis::streams::StreamPendingReply::Data(pending) =
+            redis.stream_pending_summary(JOBS_STREAM, GROUP).await?
+        {
+            stats.running = pending.count as u64;
+            stats.consumers = pending
+                .consumers
+                .into_iter()
+                .map(|consumer| (consumer.name, consumer.pending as u64))
+                .collect();
+        }
+        // handled jobs are deleted, the stream only holds queued and running ones.
+        stats.queued = redis
+            .stream_len(JOBS_STREAM)
+            .await?
+            .saturating_sub(stats.running);
+        stats.delayed = redis.sorted_set_len(DELAYED_JOBS).await?;
+        stats.dead = redis.stream_len(DEAD_JOBS).await?;
+        Ok(stats)
+    }
+
+    async fn work(&self, consumer: String) {
+        loop {
+            // one at a time: a fetched job is pending, thus getting closer
+            //     to `VISIBILITY_TIMEOUT`, even while it waits for the ones before it.
+            let entries = self
+                .database
+                .redis
+                .stream_read(JOBS_STREAM, GROUP, &consumer, 1)
+                .await;
+            match entries {
+                Err(err) => {
+                    log::warn!("[jobs] failed to fetch jobs: {:?}", err);
+                    tokio::time::sleep(POLL_INTERVAL).await;
+                }
+                Ok(entries) if entries.is_empty() => tokio::time::sleep(POLL_INTERVAL).await,
+                Ok(entries) => {
+                    for entry in entries.iter() {
+                        self.handle(&consumer, entry).await;
+                    }
+                }
+            };
+        }
+    }
+
+    async fn handle(&self, consumer: &str, entry: &StreamId) {
+        let mut envelope = match Envelope::decode(entry) {
+            Err(err) => {
+                log::error!("[jobs] dropping malformed job {}: {:?}", entry.id, err);
+                self.ack(&entry.id).await;
+                return;
+            }
+            Ok(envelope) => envelope,
+        };
+        let result = match self.handlers.get(envelope.kind.as_str()) {
+            None => Err(DatabaseError::Other(format!(
+                "no handler for {:?}",
+                envelope.kind
+            ))),
+            // in its own task, so a panic fails the job rather than the worker.
+            Some(handler) => {
+                let mut run = tokio::spawn(handler(self.database.clone(), envelope.job.clone()));
+                let mut heartbeat = tokio::time::interval(HEARTBEAT_INTERVAL);
+                heartbeat.tick().await;
+                loop {
+                    tokio::select! {
+                        joined = &mut run => break match joined {
+                            Err(err) => Err(DatabaseError::Other(format!("panicked: {:?}", err))),
+                            Ok(result) => result,
+                        },
+                        _ = heartbeat.tick() => self.touch(consumer, &entry.id).await,
+                    }
+                }
+            }
+        };
+        if let Err(err) = result {
+            log::warn!(
+                "[jobs] {} {} failed (attempt {}): {:?}",
+                envelope.kind,
+                entry.id,
+                envelope.attempt + 1,
+                err
+            );
+            envelope.attempt += 1;
+            envelope.error = Some(format!("{:?}", err));
+            // rescheduled before being acknowledged: a crash in between
+            //     runs it twice rather than losing it.
+            if let Err(err) = self.reschedule(&envelope).await {
+                // left pending, it'll be recovered once abandoned for long enough.
+                log::error!("[jobs] failed to reschedule {}: {:?}", entry.id, err);
+                return;
+            }
+        }
+        self.ack(&entry.id).await;
+    }
+
+    /// Retry a failed job later, or give up on it.
+    async fn reschedule(&self, envelope: &Envelope) -> Result<(), DatabaseError> {
+        let redis = &self.database.redis;
+        if envelope.attempt >= MAX_ATTEMPTS {
+            log::error!(
+                "[jobs] giving up on {} {} after {} attempts",
+                envelope.kind,
+                envelope.id,
+                envelope.attempt
+            );
+            redis
+                .stream_add(DEAD_JOBS, MAX_STREAM_LEN, JOB_FIELD, envelope.encode()?)
+                .await?;
+            return Ok(());
+        }
+        redis
+            .delay(DELAYED_JOBS, backoff(envelope.attempt), envelope.encode()?)
+            .await
+    }
+
+    /// Tell `recover` that a long job is still running, not abandoned.
+    async fn touch(&self, consumer: &str, id: &str) {
+        let ids = vec![id.to_owned()];
+        if let Err(err) = self
+            .database
+            .redis
+            .stream_touch(JOBS_STREAM, GROUP, consumer, &ids)
+            .await
+        {
+            log::warn!("[jobs] failed to keep {} alive: {:?}", id, err);
+        }
+    }
+
+    async fn ack(&self, id: &str) {
+        let ids = vec![id.to_owned()];
+        if let Err(err) = self
+            .database
+            .redis
+            .stream_ack(JOBS_STREAM, GROUP, &ids)
+            .await
+        {
+            log::warn!("[jobs] failed to acknowledge {}: {:?}", id, err);
+        }
+    }
+
+    /// Put abandoned jobs back in the queue, or dead-letter the ones
+    ///     that keep being abandoned.
+    /// Every pending job is looked at, abandoned ones may sit behind live ones.
+    async fn recover(&self, consumer: &str) -> Result<usize, DatabaseError> {
+        let mut recovered = 0;
+        let mut start = "-".to_owned();
+        loop {
+            let abandoned: Vec<String> = self
+                .pending(VISIBILITY_TIMEOUT, &start, BATCH_SIZE)
+                .await?
+                .into_iter()
+                .map(|pending| pending.id)
+                .collect();
+            let last = match abandoned.last() {
+                None => break,
+                Some(last) => last.clone(),
+            };
+            recovered += self.requeue(consumer, &abandoned).await?;
+            if abandoned.len() < BATCH_SIZE {
+                break;
+            }
+            start = format!("({}", last);
+        }
+        Ok(recovered)
+    }
+
+    /// Claim abandoned jobs, then queue them again (or dead-letter them),
+    ///     return how many have been claimed.
+    async fn requeue(&self, consumer: &str, abandoned: &[String]) -> Result<usize, DatabaseError> {
+        let redis = &self.database.redis;
+        let claimed = redis
+            .stream_claim(JOBS_STREAM, GROUP, consumer, VISIBILITY_TIMEOUT, abandoned)
+            .await?;
+        for entry in claimed.iter() {
+            let mut envelope = match Envelope::decode(entry) {
+                Err(err) => {
+                    log::error!("[jobs] dropping malformed job {}: {:?}", entry.id, err);
+                    self.ack(&entry.id).await;
+                    continue;
+                }
+                Ok(envelope) => envelope,
+            };
+            // re-added with a new id, so the count has to travel with the job.
+            envelope.abandoned += 1;
+            let stream = if envelope.abandoned >= MAX_ABANDONED {
+                log::error!(
+                    "[jobs] {} {} keeps being abandoned, giving up on it",
+                    envelope.kind,
+                    envelope.id
+                );
+                envelope.error = Some(format!("abandoned {} times", envelope.abandoned));
+                DEAD_JOBS
+            } else {
+                JOBS_STREAM
+            };
+            redis
+                .stream_add(stream, MAX_STREAM_LEN, JOB_FIELD, envelope.encode()?)
+                .await?;
+            self.ack(&entry.id).await;
+        }
+        Ok(claimed.len())
+    }
+
+    /// Release delayed jobs that are due, recover abandoned ones,
+    ///     and log how the queue is doing.
+    async fn maintain(&self) {
+        let consumer = format!("{}:maintenance", self.instance);
+        let mut stats = tokio::time::interval(STATS_INTERVAL);
+        loop {
+            tokio::select! {
+                _ = tokio::time::sleep(POLL_INTERVAL) => {
+                    let released = self
+                        .database
+                        .redis
+                        .release_delayed(DELAYED_JOBS, JOBS_STREAM, MAX_STREAM_LEN, JOB_FIELD, BATCH_SIZE)
+                        .await;
+                    if let Err(err) = released {
+                        log::warn!("[jobs] failed to release delayed jobs: {:?}", err);
+                    }
+                    match self.recover(&consumer).await {
+                        Err(err) => log::warn!("[jobs] failed to recover abandoned jobs: {:?}", err),
+                        Ok(0) => {}
+                        Ok(recovered) => log::warn!("[jobs] recovered {} abandoned job(s)", recovered),
+                    }
+                },
+                _ = stats.tick() => match self.stats().await {
+                    Err(err) => log::warn!("[jobs] failed to read the queue: {:?}", err),
+                    Ok(stats) if stats.queued + stats.running + stats.delayed + stats.dead == 0 => {}
+                    Ok(stats) => log::info!("[jobs] {:?}", stats),
+                },
+            }
+        }
+    }
+}
+
+/// Background loop, run queued jobs with `workers` concurrent workers.
+pub async fn run(jobs: Jobs, workers: usize) {
+    if let Err(err) = jobs.database.redis.stream_group(JOBS_STREAM, GROUP).await {
+        log::error!("[jobs] failed to create the consumer group: {:?}", err);
+    }
+    let jobs = Arc::new(jobs);
+    let mut tasks = vec![];
+    for worker in 0..workers {
+        let jobs = jobs.clone();
+        let consumer = format!("{}:{}", jobs.instance, worker);
+        tasks.push(tokio::spawn(async move { jobs.work(consumer).await }));
+    }
+    tasks.push(tokio::spawn(async move { jobs.maintain().await }));
+    futures::future::join_all(tasks).await;
+}
+
+/// Every kind of job this binary knows how to run.
+pub fn handlers(database: Arc<Database>) -> Jobs {
+    Jobs::new(database).register(|database, _: RebuildTitles| async move {
+        let indexer = Indexer::new(database);
+        // queued by every instance starting with the new settings, only the first
+        //     one to run has anything left to do.
+        if !indexer.titles_settings_changed().await? {
+            return Ok(());
+        }
+        // someone else rebuilding gets the job done just as well.
+        if !indexer.rebuild().await? {
+            log::info!("[jobs] titles are already being rebuilt by another instance");
+        }
+        Ok(())
+    })
+}
+
+/// How long to wait before the next attempt, after `attempt` failed ones.
+fn backoff(attempt: u32) -> u64 {
+    BACKOFF_BASE
+        .saturating_mul(1 << attempt.saturating_sub(1).min(20))
+        .min(BACKOFF_MAX)
+}
+
+/// Identify a job or a worker, unique enough across replicas.
+fn token() -> String {
+    let nanos = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|now| now.as_nanos())
+        .unwrap_or(0);
+    format!("{}:{}", std::process::id(), nanos)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn backoff_doubles_up_to_the_max() {
+        assert_eq!(backoff(1), BACKOFF_BASE);
+        assert_eq!(backoff(2), BACKOFF_BASE * 2);
+        assert_eq!(backoff(3), BACKOFF_BASE * 4);
+        assert_eq!(backoff(MAX_ATTEMPTS), BACKOFF_BASE * 16);
+        assert_eq!(backoff(40), BACKOFF_MAX);
+        assert_eq!(backoff(u32::MAX), BACKOFF_MAX);
+    }
+}
@@ -2,20 +2,36 @@ use super::{
     error::DatabaseError,
     sync::{SyncResponse, SyncSupport},
 };
-use crate::server_config::manticore::ManticoreConfig;
+use crate::server_config::manticore::{ManticoreConfig, ManticoreTransport};
 use mysql::{prelude::*, Conn, Error as MySqlError, OptsBuilder};
-use r2d2::{Error, Pool, PooledConnection};
+use r2d2::{Pool, PooledConnection};
 use r2d2_mysql::MySqlConnectionManager;
-use std::sync::Arc;
+use std::{
+    sync::{Arc, RwLock},
+    time::Duration,
+};
 
+pub mod http;
+pub mod metadata;
+pub mod query;
+pub mod rebuild;
 pub mod schema;
+pub mod search;
+pub mod text;
+
+use http::HttpClient;
+use query::{Param, Query};
+use rebuild::TitlesTables;
 
-const POOL_MAX_SIZE: u32 = 5;
-const SPECIAL_TOKEN: &'static [(&str, &str)] = &[("\\", "\\\\"), ("'", "\\'"), ("\"", "\\\"")];
+const SCHEMA_VERSION_KEY: &str = "schema_version";
 
 pub struct ManticoreWrapper {
     pool: Arc<Pool<MySqlConnectionManager>>,
     pub prefix: String,
+    pub wordforms: Option<String>,
+    /// Set when search and indexing should go through the JSON api.
+    pub http: Option<HttpClient>,
+    titles: RwLock<TitlesTables>,
 }
 
 fn healthcheck(_: MySqlConnectionManager, conn: &mut Conn) -> Result<(), MySqlError> {
@@ -23,10 +39,11 @@ fn healthcheck(_: MySqlConnectionManager, conn: &mut Conn) -> Result<(), MySqlEr
 }
 
 impl ManticoreWrapper {
-    pub async fn new(config: &ManticoreConfig) -> Result<Self, Error> {
+    pub async fn new(config: &ManticoreConfig) -> Result<Self, DatabaseError> {
         let builder = OptsBuilder::new()
             .ip_or_hostname(Some(&config.uri))
-            .tcp_port(config.port);
+            .tcp_port(config.port)
+            .tcp_connect_timeout(Some(Duration::from_millis(config.connect_timeout)));
         let builder = if config.had_auth() {
             builder
                 .user(Some(&config.user))
@@ -35,28 +52,76 @@ impl ManticoreWrapper {
             builder
         };
         let manager = MySqlConnectionManager::with_custom_healthcheck(builder, &healthcheck);
-        let pool = Pool::builder().max_size(POOL_MAX_SIZE).build(manager)?;
-        Ok(Self {
+        let idle_timeout = match config.idle_timeout {
+            0 => None,
+            timeout => Some(Duration::from_millis(timeout)),
+        };
+        let pool = Pool::builder()
+            .max_size(config.pool_max)
+            .connection_timeout(Duration::from_millis(config.connect_timeout))
+            .idle_timeout(idle_timeout)
+            .build(manager)?;
+        let manticore = Self {
             pool: Arc::new(pool),
             prefix: String::from(&config.prefix),
-        })
+            wordforms: config.wordforms.clone(),
+            http: match config.transport {
+                ManticoreTransport::Sql => None,
+                ManticoreTransport::Http => Some(HttpClient::new(config)?),
+            },
+            titles: RwLock::new(TitlesTables::default()),
+        };
+        if let Err(err) = manticore.refresh_titles_tables() {
+            log::warn!("[manticore] failed to load the titles tables: {:?}", err);
+        }
+        Ok(manticore)
+    }
+
+    /// Resolve a table name, with the configured prefix.
+    pub fn table(&self, name: &str) -> String {
+        format!("{}{}", self.prefix, name)
     }
 
     pub fn conn(&self) -> Result<PooledConnection<MySqlConnectionManager>, DatabaseError> {
         let pool = self.pool.clone();
         Ok(pool.get()?)
     }
+
+    /// Run blocking manticore calls on tokio's blocking threads,
+    ///     so async callers (e.g. graphql resolvers) don't stall the reactor.
+    ///
+    /// ```ignore
+    /// let result = manticore
+    ///     .blocking(move |manticore| manticore.search_titles(&keywords, &filters, 0, 20))
+    ///     .await?;
+    /// ```
+    pub async fn blocking<T, F>(self: &Arc<Self>, f: F) -> Result<T, DatabaseError>
+    where
+        T: Send + 'static,
+        F: FnOnce(&ManticoreWrapper) -> Result<T, DatabaseError> + Send + 'static,
+    {
+        let manticore = self.clone();
+        match tokio::task::spawn_blocking(move || f(&manticore)).await {
+            Err(err) => Err(DatabaseError::Other(format!(
+                "manticore task failed: {:?}",
+                err
+            ))),
+            Ok(result) => result,
+        }
+    }
 }
 
-/// @fixme - mid priority
-/// potential SQL-injection? either better sanitizer or
-/// somehow make manticore support prepared statement.
-pub fn sanitize_param<T: Into<String>>(param: T) -> String {
-    let mut sanitized = Into::<String>::into(param);
-    for token in SPECIAL_TOKEN {
-        sanitized = sanitized.replace(token.0, token.1);
+impl ManticoreWrapper {
+    /// The schema version used to be kept in `sync_data`, until schema #6.
+    fn legacy_schema_version(&self) -> Result<Option<String>, DatabaseError> {
+        let query = Query::new("SELECT value FROM ? WHERE MATCH('@field schema_version')")
+            .bind(Param::Ident(self.table("sync_data")))
+            .build()?;
+        match self.conn()?.query_first(query).map_err(DatabaseError::from) {
+            Err(DatabaseError::ManticoreMissingTable(_)) => Ok(None),
+            result => result,
+        }
     }
-    sanitized
 }
 
 impl SyncSupport for ManticoreWrapper {
@@ -65,24 +130,14 @@ impl SyncSupport for ManticoreWrapper {
     }
 
     fn schema_version(&self) -> Result<Option<i64>, DatabaseError> {
-        const NO_TABLE_ERROR: &str = "unknown local table(s)";
-        const VERSION_QUERY: &str = r#"
-            SELECT value 
-            FROM sync_data 
-            WHERE MATCH('@field schema_version')
-        "#;
-
-        let mut conn = self.conn()?;
-        let row: Option<String> = match conn.query_first(VERSION_QUERY) {
-            Err(err) => {
-                if format!("{:?}", err).contains(NO_TABLE_ERROR) {
-                    return Ok(None);
-                }
-                return Err(err.into());
+        // also fall back when schema #6 has run but couldn't be recorded yet.
+        let version = match self.get_metadata(SCHEMA_VERSION_KEY) {
+            Ok(None) | Err(DatabaseError::ManticoreMissingTable(_)) => {
+                self.legacy_schema_version()?
             }
-            Ok(row) => row,
+            result => result?,
         };
-        let version = match row {
+        let version = match version {
             None => return Ok(None),
             Some(version) => version,
         };
@@ -93,22 +148,7 @@ impl SyncSupport for ManticoreWrapper {
     }
 
     fn set_schema_version(&self, version: i64) -> SyncResponse {
-        let mut conn = self.conn()?;
-        match self.schema_version()? {
-            None => {
-                conn.query_drop(format!(
-                    "INSERT INTO sync_data (field, value) VALUES ('{}', '{}')",
-                    "schema_version", version
-                ))?;
-            }
-            Some(_) => {
-                conn.query_drop(format!(
-                    "UPDATE sync_data SET value = '{}' WHERE MATCH('@field {}')",
-                    version, "schema_version"
-                ))?;
-            }
-        };
-        Ok(())
+        self.set_metadata(SCHEMA_VERSION_KEY, &version.to_string())
     }
 
     fn execute(&self, query: &str) -> SyncResponse {
@@ -0,0 +1,181 @@
+use crate::database::error::DatabaseError;
+use crate::server_config::manticore::ManticoreConfig;
+use reqwest::{Client, Response};
+use serde::{de::DeserializeOwned, Deserialize};
+use serde_json::{Map, Value};
+use std::{collections::HashMap, time::Duration};
+
+/// A client for manticore's JSON api, queries are sent as structured json
+///     so nothing needs to be escaped into an SQL string.
+pub struct HttpClient {
+    client: Client,
+    base: String,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct SearchResponse {
+    pub hits: SearchHits,
+    #[serde(default)]
+    pub aggregations: HashMap<String, Aggregation>,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct SearchHits {
+    pub total: u64,
+    pub hits: Vec<SearchHit>,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct SearchHit {
+    /// Older versions of manticore send the id as a string.
+    #[serde(rename = "_id")]
+    pub id: Value,
+    #[serde(rename = "_score", default)]
+    pub score: i64,
+    #[serde(rename = "_source", default)]
+    pub source: Map<String, Value>,
+    #[serde(default)]
+    pub highlight: HashMap<String, Vec<String>>,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct Aggregation {
+    pub buckets: Vec<Bucket>,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct Bucket {
+    pub key: Value,
+    pub doc_count: i64,
+}
+
+#[derive(Deserialize, Debug)]
+struct BulkResponse {
+    #[serde(default)]
+    errors: bool,
+    #[serde(default)]
+    items: Vec<Value>,
+}
+
+impl SearchHit {
+    pub fn id(&self) -> Result<u64, DatabaseError> {
+        let id = match &self.id {
+            Value::Number(id) => id.as_u64(),
+            Value::String(id) => id.parse::<u64>().ok(),
+            _ => None,
+        };
+        id.ok_or_else(|| DatabaseError::ManticoreHttpError(format!("invalid id: {}", self.id)))
+    }
+}
+
+impl HttpClient {
+    pub fn new(config: &ManticoreConfig) -> Result<Self, DatabaseError> {
+        let client = Client::builder()
+            .connect_timeout(Duration::from_millis(config.connect_timeout))
+            .timeout(Duration::from_millis(config.request_timeout))
+            .pool_max_idle_per_host(config.pool_max as usize)
+            .pool_idle_timeout(match config.idle_timeout {
+                0 => None,
+                timeout => Some(Duration::from_millis(timeout)),
+            })
+            .build()?;
+        Ok(Self {
+            client,
+            base: config.http_uri.clone(),
+        })
+    }
+
+    fn url(&self, endpoint: &str) -> String {
+        format!("{}/{}", self.base, endpoint)
+    }
+
+    pub async fn search(&self, body: &Value) -> Result<SearchResponse, DatabaseError> {
+        let response = self
+            .client
+            .post(self.url("search"))
+            .json(body)
+            .send()
+            .await?;
+        parse(response).await
+    }
+
+    /// Insert a single document, or overwrite it if `replace` is set.
+    pub async fn insert(
+        &self,
+        table: &str,
+        id: u64,
+        doc: Value,
+        replace: bool,
+    ) -> Result<(), DatabaseError> {
+        let endpoint = if replace { "replace" } else { "insert" };
+        let body = serde_json::json!({ "index": table, "id": id, "doc": doc });
+        let response = self
+            .client
+            .post(self.url(endpoint))
+            .json(&body)
+            .send()
+            .await?;
+        parse::<Value>(response).await?;
+        Ok(())
+    }
+
+    /// Send many actions at once (as NDJSON), e.g.
+    ///     `{"replace": {"index": "titles", "id": 1, "doc": {...}}}`.
+    pub async fn bulk(&self, actions: &[Value]) -> Result<(), DatabaseError> {
+        if actions.is_empty() {
+            return Ok(());
+        }
+        let mut body = String::new();
+        for action in actions.iter() {
+            body.push_str(&action.to_string());
+            body.push('\n');
+        }
+        let response = self
+            .client
+            .post(self.url("bulk"))
+            .header(reqwest::header::CONTENT_TYPE, "application/x-ndjson")
+            .body(body)
+            .send()
+            .await?;
+        let bulk = parse::<BulkResponse>(response).await?;
+        if !bulk.errors {
+            return Ok(());
+        }
+        // the first failed item is enough to know what went wrong.
+        let error = bulk
+            .items
+            .iter()
+            .filter_map(|item| item.as_object()?.values().next()?.get("error"))
+            .next()
+            .map(error_message)
+            .unwrap_or("bulk request failed".to_owned());
+        Err(DatabaseError::from_manticore_http(error))
+    }
+}
+
+async fn parse<T: DeserializeOwned>(response: Response) -> Result<T, DatabaseError> {
+    let status = response.status();
+    let body: Value = response.json().await?;
+    if let Some(error) = body.get("error").filter(|error| !error.is_null()) {
+        return Err(DatabaseError::from_manticore_http(error_message(error)));
+    }
+    if !status.is_success() {
+        return Err(DatabaseError::ManticoreHttpError(format!(
+            "{}: {}",
+            status, body
+        )));
+    }
+    serde_json::from_value(body).map_err(|err| DatabaseError::ManticoreHttpError(err.to_string()))
+}
+
+/// Errors are either a plain string or an object with a `reason`.
+fn error_message(error: &Value) -> String {
+    match error {
+        Value::String(message) => message.clone(),
+        Value::Object(object) => match object.get("reason") {
+            Some(Value::String(reason)) => reason.clone(),
+            _ => error.to_string(),
+        },
+        _ => error.to_string(),
+    }
+}
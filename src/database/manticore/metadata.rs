@@ -0,0 +1,83 @@
+use super::{
+    query::{Param, Query},
+    ManticoreWrapper,
+};
+use crate::database::error::DatabaseError;
+use mysql::prelude::*;
+
+/// Key-value settings (e.g. the schema version), one document per key.
+///
+/// String attributes can't be updated in place and matching the key as
+///     full-text is fragile, so every key has a fixed id instead and is
+///     written with `REPLACE INTO`.
+impl ManticoreWrapper {
+    pub fn get_metadata(&self, key: &str) -> Result<Option<String>, DatabaseError> {
+        let query = Query::new("SELECT value FROM ? WHERE id = ?")
+            .bind(Param::Ident(self.table("metadata")))
+            .bind(Param::UInt(metadata_id(key)))
+            .build()?;
+        Ok(self.conn()?.query_first(query)?)
+    }
+
+    pub fn set_metadata(&self, key: &str, value: &str) -> Result<(), DatabaseError> {
+        let query = Query::new("REPLACE INTO ? (id, name, value) VALUES (?, ?, ?)")
+            .bind(Param::Ident(self.table("metadata")))
+            .bind(Param::UInt(metadata_id(key)))
+            .bind(Param::Str(key.to_owned()))
+            .bind(Param::Str(value.to_owned()))
+            .build()?;
+        self.conn()?.query_drop(query)?;
+        Ok(())
+    }
+
+    pub fn delete_metadata(&self, key: &str) -> Result<(), DatabaseError> {
+        let query = Query::new("DELETE FROM ? WHERE id = ?")
+            .bind(Param::Ident(self.table("metadata")))
+            .bind(Param::UInt(metadata_id(key)))
+            .build()?;
+        self.conn()?.query_drop(query)?;
+        Ok(())
+    }
+}
+
+/// Id of the document holding `key`: its 64-bit FNV-1a hash, which never
+///     changes between builds (unlike std's hasher).
+/// Kept within `1..=i64::MAX`, manticore doesn't accept 0 nor "negative" ids.
+pub fn metadata_id(key: &str) -> u64 {
+    let mut hash: u64 = 0xcbf29ce484222325;
+    for byte in key.bytes() {
+        hash ^= byte as u64;
+        hash = hash.wrapping_mul(0x100000001b3);
+    }
+    match hash & (i64::MAX as u64) {
+        0 => 1,
+        id => id,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn metadata_id_is_stable() {
+        assert_eq!(metadata_id(""), 0xcbf29ce484222325 & i64::MAX as u64);
+        assert_eq!(metadata_id("a"), 0xaf63dc4c8601ec8c & i64::MAX as u64);
+        assert_eq!(metadata_id("schema_version"), metadata_id("schema_version"));
+    }
+
+    #[test]
+    fn metadata_id_is_positive_and_distinct() {
+        let keys = [
+            "schema_version",
+            "titles_version",
+            "schema_versio",
+            "Schema_version",
+        ];
+        let ids: Vec<u64> = keys.iter().map(|key| metadata_id(key)).collect();
+        for (i, id) in ids.iter().enumerate() {
+            assert!(*id > 0 && *id <= i64::MAX as u64);
+            assert!(!ids[i + 1..].contains(id));
+        }
+    }
+}
@@ -0,0 +1,364 @@
+use crate::database::error::DatabaseError;
+
+/// Characters with a meaning inside a `MATCH()` full-text query,
+///     including the wildcards used with infix/prefix indexes.
+const MATCH_OPERATORS: &[char] = &[
+    '\\', '!', '"', '$', '\'', '(', ')', '-', '/', '<', '=', '@', '^', '|', '~', '*', '?', '%',
+];
+
+/// A value to be bound into a SphinxQL query.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Param {
+    Int(i64),
+    UInt(u64),
+    // no float nor boolean attribute is queried yet.
+    #[allow(dead_code)]
+    Float(f64),
+    #[allow(dead_code)]
+    Bool(bool),
+    /// A plain string literal.
+    Str(String),
+    /// User input for a `MATCH()` clause, every full-text operator is escaped
+    ///     so it can only ever be searched as keywords.
+    /// Searches build their own full-text query (see `text.rs`) and use `RawMatch`.
+    #[allow(dead_code)]
+    Match(String),
+    /// A raw full-text query built by us (e.g. with field operators),
+    ///     only escaped as a string literal.
+    RawMatch(String),
+    /// A table or column name.
+    Ident(String),
+    /// A list of values, for `IN (?)`.
+    List(Vec<Param>),
+}
+
+/// A SphinxQL query with `?` placeholders.
+///
+/// Manticore doesn't support server-side prepared statements over the mysql
+///     protocol, so every parameter is escaped according to its type instead.
+///
+/// ```ignore
+/// let query = Query::new("SELECT id FROM ? WHERE MATCH(?) AND publisher IN (?) LIMIT ?")
+///     .bind(Param::Ident(table))
+///     .bind(Param::Match(keywords))
+///     .bind(Param::List(publishers))
+///     .bind(Param::UInt(limit))
+///     .build()?;
+/// ```
+#[derive(Debug, Clone)]
+pub struct Query {
+    template: String,
+    params: Vec<Param>,
+}
+
+impl Query {
+    pub fn new<T: Into<String>>(template: T) -> Self {
+        Self {
+            template: template.into(),
+            params: vec![],
+        }
+    }
+
+    pub fn bind(mut self, param: Param) -> Self {
+        self.params.push(param);
+        self
+    }
+
+    /// Render the final query, placeholders inside string literals of the
+    ///     template are left untouched.
+    pub fn build(&self) -> Result<String, DatabaseError> {
+        let mut query = String::with_capacity(self.template.len());
+        let mut params = self.params.iter();
+        let mut quote: Option<char> = None;
+        let mut escaped = false;
+        for c in self.template.chars() {
+            match quote {
+                Some(q) => {
+                    if escaped {
+                        escaped = false;
+                    } else if c == '\\' {
+                        escaped = true;
+                    } else if c == q {
+                        quote = None;
+                    }
+                    query.push(c);
+                }
+                None if c == '\'' || c == '"' => {
+                    quote = Some(c);
+                    query.push(c);
+                }
+                None if c == '?' => match params.next() {
+                    None => {
+                        return Err(DatabaseError::Other(format!(
+                            "not enough parameters for query: {}",
+                            self.template
+                        )))
+                    }
+                    Some(param) => query.push_str(&render(param)?),
+                },
+                None => query.push(c),
+            };
+        }
+        if params.next().is_some() {
+            return Err(DatabaseError::Other(format!(
+                "too many parameters for query: {}",
+                self.template
+            )));
+        }
+        Ok(query)
+    }
+}
+
+fn render(param: &Param) -> Result<String, DatabaseError> {
+    Ok(match param {
+        Param::Int(value) => value.to_string(),
+        Param::UInt(value) => value.to_string(),
+        Param::Float(value) => {
+            if !value.is_finite() {
+                return Err(DatabaseError::Other(format!(
+                    "can't bind a non-finite float: {}",
+                    value
+                )));
+            }
+            format!("{:?}", value)
+        }
+        Param::Bool(value) => (if *value { "1" } else { "0" }).to_owned(),
+        Param::Str(value) => format!("'{}'", escape_string(value)),
+        Param::Match(value) => format!("'{}'", escape_string(&escape_match(value))),
+        Param::RawMatch(value) => format!("'{}'", escape_string(value)),
+        Param::Ident(value) => {
+            if !is_ident(value) {
+                return Err(DatabaseError::Other(format!(
+                    "invalid identifier: {:?}",
+                    value
+                )));
+            }
+            value.to_owned()
+        }
+        Param::List(values) => {
+            if values.is_empty() {
+                return Err(DatabaseError::Other("can't bind an empty list".to_owned()));
+            }
+            values
+                .iter()
+                .map(render)
+                .collect::<Result<Vec<String>, DatabaseError>>()?
+                .join(", ")
+        }
+    })
+}
+
+/// Escape a value to be put between single quotes.
+/// NUL bytes are dropped, manticore would cut the string there anyway.
+pub fn escape_string(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    for c in value.chars() {
+        match c {
+            '\0' => {}
+            '\\' | '\'' | '"' => {
+                escaped.push('\\');
+                escaped.push(c);
+            }
+            _ => escaped.push(c),
+        };
+    }
+    escaped
+}
+
+/// Escape every full-text operator, so the value is searched as plain keywords.
+/// The result still need to go through `escape_string` to be put in a query.
+pub fn escape_match(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    for c in value.chars() {
+        if MATCH_OPERATORS.contains(&c) {
+            escaped.push('\\');
+        }
+        if c != '\0' {
+            escaped.push(c);
+        }
+    }
+    escaped
+}
+
+/// Whether the value is safe to be used as a table/column name.
+pub fn is_ident(value: &str) -> bool {
+    let mut chars = value.chars();
+    match chars.next() {
+        Some(c) if c.is_ascii_alphabetic() || c == '_' => {}
+        _ => return false,
+    };
+    chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Number of random inputs for each fuzz test.
+    const ROUNDS: usize = 2000;
+
+    /// Characters the fuzzer is biased toward.
+    const NASTY: &[char] = &[
+        '\\', '\'', '"', '?', '\0', '\n', '\r', '\t', '@', '|', '-', '!', '(', ')', '~', '/', '^',
+        '$', '=', '*', '%', '<', ';', '#', ' ', 'a', 'Z', '0', 'ệ', 'ư', 'ひ', 'カ', '漢', '🍑',
+    ];
+
+    /// A tiny xorshift generator, so the tests stay deterministic.
+    struct Rng(u64);
+
+    impl Rng {
+        fn next(&mut self) -> u64 {
+            self.0 ^= self.0 << 13;
+            self.0 ^= self.0 >> 7;
+            self.0 ^= self.0 << 17;
+            self.0
+        }
+
+        fn string(&mut self) -> String {
+            let len = (self.next() % 32) as usize;
+            (0..len)
+                .map(|_| match self.next() % 4 {
+                    0 => char::from_u32((self.next() % 0x3000) as u32).unwrap_or('x'),
+                    _ => NASTY[(self.next() % NASTY.len() as u64) as usize],
+                })
+                .collect()
+        }
+    }
+
+    /// Read a single quoted literal the way manticore's lexer does,
+    ///     return its value and whatever is left after the closing quote.
+    fn read_literal(input: &str) -> Option<(String, &str)> {
+        let mut chars = input.char_indices();
+        if chars.next()?.1 != '\'' {
+            return None;
+        }
+        let mut value = String::new();
+        while let Some((i, c)) = chars.next() {
+            match c {
+                '\'' => return Some((value, &input[i + 1..])),
+                '\\' => value.push(chars.next()?.1),
+                c => value.push(c),
+            };
+        }
+        None
+    }
+
+    /// Split a full-text query into (escaped, char) pairs.
+    fn match_chars(input: &str) -> Vec<(bool, char)> {
+        let mut result = vec![];
+        let mut chars = input.chars();
+        while let Some(c) = chars.next() {
+            match c {
+                '\\' => result.push((true, chars.next().unwrap())),
+                c => result.push((false, c)),
+            };
+        }
+        result
+    }
+
+    #[test]
+    fn escape_known_values() {
+        assert_eq!(escape_string(r#"it's"#), r#"it\'s"#);
+        assert_eq!(escape_string(r#"a\b"#), r#"a\\b"#);
+        assert_eq!(escape_string("a\0b"), "ab");
+        assert_eq!(escape_match("-dog @name"), r#"\-dog \@name"#);
+        assert_eq!(
+            render(&Param::Match("a'b".to_owned())).unwrap(),
+            r#"'a\\\'b'"#
+        );
+    }
+
+    #[test]
+    fn build_query() {
+        let query =
+            Query::new("SELECT id FROM ? WHERE MATCH(?) AND id IN (?) AND note = '?' LIMIT ?")
+                .bind(Param::Ident("titles".to_owned()))
+                .bind(Param::Match("oshi no ko".to_owned()))
+                .bind(Param::List(vec![Param::Int(1), Param::Int(2)]))
+                .bind(Param::UInt(20))
+                .build()
+                .unwrap();
+        assert_eq!(
+            query,
+            "SELECT id FROM titles WHERE MATCH('oshi no ko') AND id IN (1, 2) AND note = '?' LIMIT 20"
+        );
+    }
+
+    #[test]
+    fn build_with_wrong_parameter_count() {
+        assert!(Query::new("SELECT ?").build().is_err());
+        assert!(Query::new("SELECT 1").bind(Param::Int(1)).build().is_err());
+    }
+
+    #[test]
+    fn reject_invalid_params() {
+        assert!(render(&Param::Ident("titles; DROP TABLE titles".to_owned())).is_err());
+        assert!(render(&Param::Ident("".to_owned())).is_err());
+        assert!(render(&Param::Float(f64::NAN)).is_err());
+        assert!(render(&Param::List(vec![])).is_err());
+    }
+
+    #[test]
+    fn fuzz_string_literal_round_trip() {
+        let mut rng = Rng(0x9E3779B97F4A7C15);
+        for _ in 0..ROUNDS {
+            let input = rng.string();
+            let literal = render(&Param::Str(input.clone())).unwrap();
+            let (value, rest) = read_literal(&literal).unwrap();
+            assert_eq!(value, input.replace('\0', ""), "literal: {}", literal);
+            assert_eq!(rest, "", "literal: {}", literal);
+        }
+    }
+
+    #[test]
+    fn fuzz_match_has_no_operator() {
+        let mut rng = Rng(0xD1B54A32D192ED03);
+        for _ in 0..ROUNDS {
+            let input = rng.string();
+            let literal = render(&Param::Match(input.clone())).unwrap();
+            let (value, rest) = read_literal(&literal).unwrap();
+            assert_eq!(rest, "", "literal: {}", literal);
+            let chars = match_chars(&value);
+            for (escaped, c) in chars.iter() {
+                assert!(
+                    *escaped || !MATCH_OPERATORS.contains(c),
+                    "unescaped {:?} in {:?}",
+                    c,
+                    value
+                );
+            }
+            assert_eq!(
+                chars.into_iter().map(|(_, c)| c).collect::<String>(),
+                input.replace('\0', "")
+            );
+        }
+    }
+
+    #[test]
+    fn fuzz_query_keeps_its_shape() {
+        let mut rng = Rng(0x2545F4914F6CDD1D);
+        for _ in 0..ROUNDS {
+            let inputs = [rng.string(), rng.string(), rng.string()];
+            let query = Query::new(
+                "SELECT id FROM titles WHERE MATCH(?) AND name = ? AND tag IN (?) LIMIT 1",
+            )
+            .bind(Param::Match(inputs[0].clone()))
+            .bind(Param::Str(inputs[1].clone()))
+            .bind(Param::List(vec![Param::Str(inputs[2].clone())]))
+            .build()
+            .unwrap();
+
+            let rest = query
+                .strip_prefix("SELECT id FROM titles WHERE MATCH(")
+                .unwrap();
+            let (_, rest) = read_literal(rest).unwrap();
+            let rest = rest.strip_prefix(") AND name = ").unwrap();
+            let (name, rest) = read_literal(rest).unwrap();
+            let rest = rest.strip_prefix(" AND tag IN (").unwrap();
+            let (tag, rest) = read_literal(rest).unwrap();
+            assert_eq!(rest, ") LIMIT 1", "query: {}", query);
+            assert_eq!(name, inputs[1].replace('\0', ""));
+            assert_eq!(tag, inputs[2].replace('\0', ""));
+        }
+    }
+}
@@ -0,0 +1,143 @@
+use super::{metadata::metadata_id, schema, ManticoreWrapper};
+use crate::database::{error::DatabaseError, sync::SyncSupport};
+
+/// Version of the table searches are served from.
+const TITLES_VERSION_KEY: &str = "titles_version";
+
+/// Version of the table being rebuilt, if any.
+const TITLES_REBUILD_KEY: &str = "titles_rebuild";
+
+/// Hash of the settings the current table has been built with.
+const TITLES_SETTINGS_KEY: &str = "titles_settings";
+
+/// Which physical tables hold the titles.
+///
+/// `titles` is the table created by the synchronizers (version 0),
+///     every rebuild creates `titles_v1`, `titles_v2`... next to it,
+///     then switch the version in the metadata store once it's filled.
+/// A single `REPLACE INTO` is atomic, unlike redefining a distributed table,
+///     which also can't be written to.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct TitlesTables {
+    pub version: u64,
+    pub rebuild: Option<u64>,
+}
+
+impl ManticoreWrapper {
+    fn titles_table_of(&self, version: u64) -> String {
+        match version {
+            0 => self.table("titles"),
+            version => self.table(&format!("titles_v{}", version)),
+        }
+    }
+
+    /// The table searches should go to.
+    pub fn titles_table(&self) -> String {
+        let tables = self.titles.read().unwrap_or_else(|err| err.into_inner());
+        self.titles_table_of(tables.version)
+    }
+
+    /// Every table writes should go to, including the one being rebuilt,
+    ///     so no update is lost while it's being filled.
+    pub fn titles_write_tables(&self) -> Vec<String> {
+        let tables = self.titles.read().unwrap_or_else(|err| err.into_inner());
+        std::iter::once(tables.version)
+            .chain(tables.rebuild)
+            .map(|version| self.titles_table_of(version))
+            .collect()
+    }
+
+    /// Reload the versions from the metadata store, other instances may have
+    ///     started or finished a rebuild.
+    pub fn refresh_titles_tables(&self) -> Result<(), DatabaseError> {
+        let tables = TitlesTables {
+            version: self.get_version(TITLES_VERSION_KEY)?.unwrap_or(0),
+            rebuild: self.get_version(TITLES_REBUILD_KEY)?,
+        };
+        let mut current = self.titles.write().unwrap_or_else(|err| err.into_inner());
+        if *current != tables {
+            log::info!("[manticore] titles tables are now {:?}", tables);
+            *current = tables;
+        }
+        Ok(())
+    }
+
+    fn get_version(&self, key: &str) -> Result<Option<u64>, DatabaseError> {
+        match self.get_metadata(key) {
+            Err(DatabaseError::ManticoreMissingTable(_)) => Ok(None),
+            Err(err) => Err(err),
+            Ok(None) => Ok(None),
+            Ok(Some(version)) => match version.parse::<u64>() {
+                Err(err) => Err(DatabaseError::Other(format!("{:?}", err))),
+                Ok(version) => Ok(Some(version)),
+            },
+        }
+    }
+
+    fn titles_settings(&self) -> String {
+        metadata_id(&schema::titles(self, "")).to_string()
+    }
+
+    /// Statement recording the settings of the table created by `schema::master`,
+    ///     so a fresh install isn't rebuilt right away.
+    pub fn titles_settings_statement(&self) -> String {
+        format!(
+            "REPLACE INTO {} (id, name, value) VALUES ({}, '{}', '{}')",
+            self.table("metadata"),
+            metadata_id(TITLES_SETTINGS_KEY),
+            TITLES_SETTINGS_KEY,
+            self.titles_settings()
+        )
+    }
+
+    /// Whether `schema::titles` has changed since the current table was built.
+    /// Tables built before this was tracked come from older synchronizers
+    ///     (see `schema::v_4`), they need a rebuild.
+    pub fn titles_settings_changed(&self) -> Result<bool, DatabaseError> {
+        match self.get_metadata(TITLES_SETTINGS_KEY)? {
+            Some(current) => Ok(current != self.titles_settings()),
+            None => Ok(true),
+        }
+    }
+
+    /// Create an empty table with the current settings and start writing to it,
+    ///     return its name once it's ready to be backfilled.
+    /// A rebuild interrupted earlier is started over.
+    pub fn start_titles_rebuild(&self) -> Result<String, DatabaseError> {
+        self.refresh_titles_tables()?;
+        let version = self
+            .titles
+            .read()
+            .unwrap_or_else(|err| err.into_inner())
+            .version
+            + 1;
+        let table = self.titles_table_of(version);
+        self.execute(&format!("DROP TABLE IF EXISTS {}", table))?;
+        self.execute(&schema::titles(self, &table))?;
+        self.set_metadata(TITLES_REBUILD_KEY, &version.to_string())?;
+        self.refresh_titles_tables()?;
+        Ok(table)
+    }
+
+    /// Serve searches from the rebuilt table, return the name of the old one,
+    ///     to be dropped once no instance uses it anymore.
+    pub fn finish_titles_rebuild(&self) -> Result<String, DatabaseError> {
+        self.refresh_titles_tables()?;
+        let (old, new) = {
+            let tables = self.titles.read().unwrap_or_else(|err| err.into_inner());
+            match tables.rebuild {
+                None => return Err(DatabaseError::Other("no rebuild in progress".to_owned())),
+                Some(rebuild) => (tables.version, rebuild),
+            }
+        };
+        self.set_metadata(TITLES_VERSION_KEY, &new.to_string())?;
+        self.delete_metadata(TITLES_REBUILD_KEY)?;
+        self.set_metadata(TITLES_SETTINGS_KEY, &self.titles_settings())?;
+        self.refresh_titles_tables()?;
+        Ok(self.titles_table_of(old))
+    }
+
+    pub fn drop_table(&self, table: &str) -> Result<(), DatabaseError> {
+        self.execute(&format!("DROP TABLE IF EXISTS {}", table))
+    }
+}
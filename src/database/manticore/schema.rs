@@ -1,18 +1,137 @@
+use super::{query::escape_string, ManticoreWrapper};
 use crate::database::sync::Synchronizer;
 use std::sync::Arc;
 
-pub fn synchronizers() -> Arc<Vec<Synchronizer>> {
-    Arc::new(vec![master(), v_1()])
+/// Every table name must go through the prefix,
+///     so several environments can share the same manticore instance.
+pub fn synchronizers(manticore: &ManticoreWrapper) -> Arc<Vec<Synchronizer>> {
+    let prefix = &manticore.prefix;
+    Arc::new(vec![
+        master(manticore),
+        v_1(prefix),
+        v_2(prefix),
+        v_3(prefix),
+        v_4(prefix),
+        v_5(prefix),
+        v_6(prefix),
+        v_7(prefix),
+    ])
 }
 
-fn master() -> Synchronizer {
+fn master(manticore: &ManticoreWrapper) -> Synchronizer {
     Synchronizer::Simple(vec![
-        "CREATE TABLE sync_data (field TEXT, value TEXT attribute)".to_owned(),
+        metadata(manticore),
+        titles(manticore, &manticore.table("titles")),
+        manticore.titles_settings_statement(),
     ])
 }
 
-fn v_1() -> Synchronizer {
-    Synchronizer::Simple(vec![
-        "CREATE TABLE sync_data (field TEXT, value TEXT attribute)".to_owned(),
-    ])
+fn v_1(prefix: &str) -> Synchronizer {
+    Synchronizer::Simple(vec![format!(
+        "CREATE TABLE {}sync_data (field TEXT, value TEXT attribute)",
+        prefix
+    )])
+}
+
+fn v_2(prefix: &str) -> Synchronizer {
+    Synchronizer::Simple(vec![format!(
+        r#"CREATE TABLE {}titles (
+            name TEXT,
+            alt_names TEXT,
+            authors TEXT,
+            tags TEXT,
+            publisher STRING ATTRIBUTE INDEXED,
+            format STRING,
+            status STRING
+        )"#,
+        prefix
+    )])
+}
+
+fn v_3(prefix: &str) -> Synchronizer {
+    Synchronizer::Simple(vec![format!(
+        "ALTER TABLE {}titles ADD COLUMN updated_at BIGINT",
+        prefix
+    )])
+}
+
+/// Tokenizer settings can't be changed in place, the indexer rebuilds the table
+///     with them (see `titles_settings_changed`) while this one keeps serving.
+/// The new field is only needed so searches and writes work in the meantime.
+fn v_4(prefix: &str) -> Synchronizer {
+    Synchronizer::Simple(vec![format!(
+        "ALTER TABLE {}titles ADD COLUMN search_names TEXT",
+        prefix
+    )])
+}
+
+fn v_5(prefix: &str) -> Synchronizer {
+    Synchronizer::Simple(vec![format!(
+        "ALTER TABLE {}titles ADD COLUMN cover STRING",
+        prefix
+    )])
+}
+
+/// The version is carried over by `schema_version`, which still reads the
+///     old table until the new one holds it, so this may run twice.
+fn v_6(prefix: &str) -> Synchronizer {
+    Synchronizer::Simple(vec![format!(
+        "CREATE TABLE IF NOT EXISTS {}metadata (name STRING, value STRING)",
+        prefix
+    )])
+}
+
+/// Only dropped once schema #6 has been recorded in the new table.
+fn v_7(prefix: &str) -> Synchronizer {
+    Synchronizer::Simple(vec![format!("DROP TABLE IF EXISTS {}sync_data", prefix)])
+}
+
+/// Key-value settings, the id of each document is derived from its `name`
+///     (see `metadata_id`), so it's written with `REPLACE INTO`.
+fn metadata(manticore: &ManticoreWrapper) -> String {
+    format!(
+        "CREATE TABLE {}metadata (name STRING, value STRING)",
+        manticore.prefix
+    )
+}
+
+/// Search index of manga/light-novel titles, `id` is the title's snowflake.
+/// `publisher` is both searchable and usable as a facet,
+///     `updated_at` mirror scylla's one, to detect stale documents.
+/// `search_names` hold the names without diacritics and with kana romanized.
+/// CJK are indexed as unigrams, and infixes are kept for `QSUGGEST`
+///     (and prefix search, used by the autocompletion).
+/// Changing anything here makes the indexer rebuild the table (see `rebuild`),
+///     a new column still need a synchronizer adding it to the current table
+///     (like `v_4` and `v_5`), which keeps serving until then.
+pub fn titles(manticore: &ManticoreWrapper, table: &str) -> String {
+    format!(
+        r#"CREATE TABLE {} (
+            name TEXT,
+            alt_names TEXT,
+            search_names TEXT,
+            authors TEXT,
+            tags TEXT,
+            publisher STRING ATTRIBUTE INDEXED,
+            format STRING,
+            status STRING,
+            cover STRING,
+            updated_at BIGINT
+        )
+        charset_table='non_cjk'
+        ngram_len='1'
+        ngram_chars='cjk'
+        min_infix_len='2'
+        expand_keywords='1'
+        {}"#,
+        table,
+        wordforms(manticore)
+    )
+}
+
+fn wordforms(manticore: &ManticoreWrapper) -> String {
+    match &manticore.wordforms {
+        None => "".to_owned(),
+        Some(path) => format!("wordforms='{}'", escape_string(path)),
+    }
 }
@@ -0,0 +1,643 @@
+use super::{
+    http::{HttpClient, SearchResponse},
+    query::{Param, Query},
+    text, ManticoreWrapper,
+};
+use crate::database::error::DatabaseError;
+use mysql::{prelude::*, Row};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::sync::Arc;
+
+/// How much a match in each field count toward the ranking.
+const FIELD_WEIGHTS: &[(&str, u32)] = &[
+    ("name", 10),
+    ("alt_names", 8),
+    ("search_names", 6),
+    ("authors", 4),
+    ("publisher", 2),
+    ("tags", 1),
+];
+
+/// `FIELD_WEIGHTS` as a SphinxQL `field_weights` option.
+fn field_weights() -> String {
+    FIELD_WEIGHTS
+        .iter()
+        .map(|(field, weight)| format!("{}={}", field, weight))
+        .collect::<Vec<String>>()
+        .join(", ")
+}
+
+/// Attributes the search results are faceted by.
+const FACETS: &[&str] = &["publisher", "format", "status"];
+
+/// Maximum number of values returned for each facet.
+const FACET_LIMIT: u32 = 20;
+
+/// "Did you mean" run one `QSUGGEST` per word,
+///     longer queries are just not corrected.
+const MAX_SUGGEST_WORDS: usize = 8;
+
+/// Longest query (in characters) that is corrected.
+const MAX_SUGGEST_LENGTH: usize = 256;
+
+/// A title, as stored in the search index.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TitleDocument {
+    pub id: u64,
+    pub name: String,
+    pub alt_names: Vec<String>,
+    pub authors: Vec<String>,
+    pub tags: Vec<String>,
+    pub publisher: String,
+    pub format: String,
+    pub status: String,
+    pub cover: String,
+    pub updated_at: i64,
+}
+
+/// An autocompletion entry.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct TitleSuggestion {
+    pub id: u64,
+    pub name: String,
+    pub cover: String,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct TitleFilters {
+    pub publishers: Vec<String>,
+    pub formats: Vec<String>,
+    pub statuses: Vec<String>,
+}
+
+#[derive(Debug, Clone)]
+pub struct TitleHit {
+    pub id: u64,
+    pub name: String,
+    /// Matched keywords wrapped in `<b></b>`, taken from the names.
+    pub highlight: String,
+    pub weight: i64,
+}
+
+#[derive(Debug, Clone)]
+pub struct FacetCount {
+    pub value: String,
+    pub count: i64,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct TitleSearchResult {
+    pub hits: Vec<TitleHit>,
+    pub total: u64,
+    pub publishers: Vec<FacetCount>,
+    pub formats: Vec<FacetCount>,
+    pub statuses: Vec<FacetCount>,
+}
+
+impl ManticoreWrapper {
+    /// Search titles by keywords, an empty `keywords` only apply the filters.
+    pub fn search_titles(
+        &self,
+        keywords: &str,
+        filters: &TitleFilters,
+        offset: u32,
+        limit: u32,
+    ) -> Result<TitleSearchResult, DatabaseError> {
+        let mut conditions: Vec<&str> = vec![];
+        let mut params: Vec<Param> = vec![];
+        if !keywords.trim().is_empty() {
+            conditions.push("MATCH(?)");
+            params.push(Param::RawMatch(text::search_expression(keywords)));
+        }
+        for (column, values) in [
+            ("publisher IN (?)", &filters.publishers),
+            ("format IN (?)", &filters.formats),
+            ("status IN (?)", &filters.statuses),
+        ] {
+            if !values.is_empty() {
+                conditions.push(column);
+                params.push(Param::List(
+                    values
+                        .iter()
+                        .map(|value| Param::Str(value.clone()))
+                        .collect(),
+                ));
+            }
+        }
+        let condition = if conditions.is_empty() {
+            "".to_owned()
+        } else {
+            format!("WHERE {}", conditions.join(" AND "))
+        };
+        let template = format!(
+            r#"
+            SELECT id, name,
+                HIGHLIGHT({{before_match='<b>', after_match='</b>'}}, 'name,alt_names') AS highlight,
+                WEIGHT() AS weight
+            FROM ?
+            {condition}
+            ORDER BY weight DESC, id DESC
+            LIMIT ?, ?
+            OPTION field_weights=({weights}), ranker=proximity_bm25
+            FACET publisher ORDER BY COUNT(*) DESC LIMIT {facet_limit}
+            FACET format ORDER BY COUNT(*) DESC LIMIT {facet_limit}
+            FACET status ORDER BY COUNT(*) DESC LIMIT {facet_limit}
+            "#,
+            condition = condition,
+            weights = field_weights(),
+            facet_limit = FACET_LIMIT,
+        );
+        let mut query = Query::new(template).bind(Param::Ident(self.titles_table()));
+        for param in params.into_iter() {
+            query = query.bind(param);
+        }
+        let query = query
+            .bind(Param::UInt(offset as u64))
+            .bind(Param::UInt(limit as u64))
+            .build()?;
+
+        let mut conn = self.conn()?;
+        let mut search = TitleSearchResult::default();
+        let mut sets = conn.query_iter(query)?;
+        let mut index = 0;
+        while let Some(set) = sets.iter() {
+            for row in set {
+                let row: Row = row?;
+                match index {
+                    0 => search.hits.push(TitleHit {
+                        id: from_row_column(&row, 0)?,
+                        name: from_row_column(&row, 1)?,
+                        highlight: from_row_column(&row, 2)?,
+                        weight: from_row_column(&row, 3)?,
+                    }),
+                    facet => {
+                        let count = FacetCount {
+                            value: from_row_column(&row, 0)?,
+                            count: from_row_column(&row, 1)?,
+                        };
+                        match facet {
+                            1 => search.publishers.push(count),
+                            2 => search.formats.push(count),
+                            _ => search.statuses.push(count),
+                        };
+                    }
+                };
+            }
+            index += 1;
+        }
+        drop(sets);
+
+        let meta: Vec<(String, String)> = conn.query("SHOW META")?;
+        search.total = meta
+            .into_iter()
+            .find(|(name, _)| name == "total_found")
+            .and_then(|(_, value)| value.parse::<u64>().ok())
+            .unwrap_or(0);
+        Ok(search)
+    }
+}
+
+impl ManticoreWrapper {
+    /// `search_titles` over the configured transport.
+    pub async fn search(
+        self: &Arc<Self>,
+        keywords: String,
+        filters: TitleFilters,
+        offset: u32,
+        limit: u32,
+    ) -> Result<TitleSearchResult, DatabaseError> {
+        match &self.http {
+            Some(http) => {
+                let table = self.titles_table();
+                search_titles_http(http, &table, &keywords, &filters, offset, limit).await
+            }
+            None => {
+                self.blocking(move |manticore| {
+                    manticore.search_titles(&keywords, &filters, offset, limit)
+                })
+                .await
+            }
+        }
+    }
+
+    /// Insert or overwrite title documents, in every table being written to.
+    pub async fn index_titles(
+        self: &Arc<Self>,
+        documents: Vec<TitleDocument>,
+    ) -> Result<(), DatabaseError> {
+        for table in self.titles_write_tables() {
+            self.index_titles_into(table, documents.clone()).await?;
+        }
+        Ok(())
+    }
+
+    /// Insert or overwrite title documents in `table` over the configured transport,
+    ///     the JSON api sends them all in a single bulk request.
+    pub async fn index_titles_into(
+        self: &Arc<Self>,
+        table: String,
+        documents: Vec<TitleDocument>,
+    ) -> Result<(), DatabaseError> {
+        match &self.http {
+            Some(http) => match documents.as_slice() {
+                [] => Ok(()),
+                [document] => {
+                    http.insert(&table, document.id, title_json(document), true)
+                        .await
+                }
+                documents => {
+                    let actions: Vec<Value> = documents
+                        .iter()
+                        .map(|document| {
+                            json!({ "replace": {
+                                "index": table,
+                                "id": document.id,
+                                "doc": title_json(document),
+                            }})
+                        })
+                        .collect();
+                    http.bulk(&actions).await
+                }
+            },
+            None => {
+                self.blocking(move |manticore| {
+                    for document in documents.iter() {
+                        manticore.replace_title(&table, document)?;
+                    }
+                    Ok(())
+                })
+                .await
+            }
+        }
+    }
+}
+
+async fn search_titles_http(
+    http: &HttpClient,
+    table: &str,
+    keywords: &str,
+    filters: &TitleFilters,
+    offset: u32,
+    limit: u32,
+) -> Result<TitleSearchResult, DatabaseError> {
+    let mut must: Vec<Value> = vec![];
+    if keywords.trim().is_empty() {
+        must.push(json!({ "match_all": {} }));
+    } else {
+        must.push(json!({ "query_string": text::search_expression(keywords) }));
+    }
+    for (attribute, values) in [
+        ("publisher", &filters.publishers),
+        ("format", &filters.formats),
+        ("status", &filters.statuses),
+    ] {
+        if !values.is_empty() {
+            let should: Vec<Value> = values
+                .iter()
+                .map(|value| json!({ "equals": { attribute: value } }))
+                .collect();
+            must.push(json!({ "bool": { "should": should } }));
+        }
+    }
+    let mut aggs = serde_json::Map::new();
+    for facet in FACETS.iter() {
+        aggs.insert(
+            facet.to_string(),
+            json!({ "terms": { "field": facet, "size": FACET_LIMIT } }),
+        );
+    }
+    let field_weights: serde_json::Map<String, Value> = FIELD_WEIGHTS
+        .iter()
+        .map(|(field, weight)| (field.to_string(), json!(weight)))
+        .collect();
+    let body = json!({
+        "index": table,
+        "query": { "bool": { "must": must } },
+        "_source": ["name"],
+        "sort": [{ "_score": "desc" }, { "id": "desc" }],
+        "offset": offset,
+        "limit": limit,
+        "highlight": {
+            "fields": ["name", "alt_names"],
+            "pre_tags": "<b>",
+            "post_tags": "</b>",
+        },
+        "options": { "ranker": "proximity_bm25", "field_weights": field_weights },
+        "aggs": aggs,
+    });
+    let response = http.search(&body).await?;
+    title_search_result(response)
+}
+
+fn title_search_result(mut response: SearchResponse) -> Result<TitleSearchResult, DatabaseError> {
+    let mut search = TitleSearchResult {
+        total: response.hits.total,
+        ..Default::default()
+    };
+    for hit in response.hits.hits.iter() {
+        let name = hit
+            .source
+            .get("name")
+            .and_then(|name| name.as_str())
+            .unwrap_or_default()
+            .to_owned();
+        // same as HIGHLIGHT() over sql: the matched name, or alternative name.
+        let highlight = ["name", "alt_names"]
+            .iter()
+            .filter_map(|field| hit.highlight.get(*field))
+            .find(|fragments| !fragments.is_empty())
+            .map(|fragments| fragments.join(" ... "))
+            .unwrap_or_else(|| name.clone());
+        search.hits.push(TitleHit {
+            id: hit.id()?,
+            name,
+            highlight,
+            weight: hit.score,
+        });
+    }
+    for (facet, counts) in FACETS.iter().zip([
+        &mut search.publishers,
+        &mut search.formats,
+        &mut search.statuses,
+    ]) {
+        let buckets = match response.aggregations.remove(*facet) {
+            None => continue,
+            Some(aggregation) => aggregation.buckets,
+        };
+        counts.extend(buckets.into_iter().map(|bucket| FacetCount {
+            value: match bucket.key {
+                Value::String(value) => value,
+                value => value.to_string(),
+            },
+            count: bucket.doc_count,
+        }));
+    }
+    Ok(search)
+}
+
+/// A title document, as sent to the JSON api.
+fn title_json(document: &TitleDocument) -> Value {
+    json!({
+        "name": document.name,
+        "alt_names": document.alt_names.join("\n"),
+        "search_names": search_names(document).join("\n"),
+        "authors": document.authors.join("\n"),
+        "tags": document.tags.join("\n"),
+        "publisher": document.publisher,
+        "format": document.format,
+        "status": document.status,
+        "cover": document.cover,
+        "updated_at": document.updated_at,
+    })
+}
+
+impl ManticoreWrapper {
+    /// Insert or overwrite a title document.
+    pub fn replace_title(
+        &self,
+        table: &str,
+        document: &TitleDocument,
+    ) -> Result<(), DatabaseError> {
+        let query = Query::new(
+            r#"
+            REPLACE INTO ? (id, name, alt_names, search_names, authors, tags, publisher, format, status, cover, updated_at)
+            VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?)
+            "#,
+        )
+        .bind(Param::Ident(table.to_owned()))
+        .bind(Param::UInt(document.id))
+        .bind(Param::Str(document.name.clone()))
+        .bind(Param::Str(document.alt_names.join("\n")))
+        .bind(Param::Str(search_names(document).join("\n")))
+        .bind(Param::Str(document.authors.join("\n")))
+        .bind(Param::Str(document.tags.join("\n")))
+        .bind(Param::Str(document.publisher.clone()))
+        .bind(Param::Str(document.format.clone()))
+        .bind(Param::Str(document.status.clone()))
+        .bind(Param::Str(document.cover.clone()))
+        .bind(Param::Int(document.updated_at))
+        .build()?;
+        self.conn()?.query_drop(query)?;
+        Ok(())
+    }
+
+    pub fn delete_titles(&self, ids: &[u64]) -> Result<(), DatabaseError> {
+        if ids.is_empty() {
+            return Ok(());
+        }
+        let mut conn = self.conn()?;
+        for table in self.titles_write_tables() {
+            let query = Query::new("DELETE FROM ? WHERE id IN (?)")
+                .bind(Param::Ident(table))
+                .bind(Param::List(ids.iter().map(|id| Param::UInt(*id)).collect()))
+                .build()?;
+            conn.query_drop(query)?;
+        }
+        Ok(())
+    }
+
+    /// `updated_at` of the indexed titles among `ids`, missing ones are not returned.
+    pub fn title_versions(&self, ids: &[u64]) -> Result<Vec<(u64, i64)>, DatabaseError> {
+        if ids.is_empty() {
+            return Ok(vec![]);
+        }
+        let query = Query::new("SELECT id, updated_at FROM ? WHERE id IN (?) LIMIT ?")
+            .bind(Param::Ident(self.titles_table()))
+            .bind(Param::List(ids.iter().map(|id| Param::UInt(*id)).collect()))
+            .bind(Param::UInt(ids.len() as u64))
+            .build()?;
+        Ok(self.conn()?.query(query)?)
+    }
+
+    /// Titles whose names start with `prefix`, best matches first.
+    pub fn suggest_titles(
+        &self,
+        prefix: &str,
+        limit: u32,
+    ) -> Result<Vec<TitleSuggestion>, DatabaseError> {
+        let query = Query::new(format!(
+            r#"
+            SELECT id, name, cover
+            FROM ?
+            WHERE MATCH(?)
+            ORDER BY WEIGHT() DESC, id DESC
+            LIMIT ?
+            OPTION field_weights=({weights})
+            "#,
+            weights = field_weights(),
+        ))
+        .bind(Param::Ident(self.titles_table()))
+        .bind(Param::RawMatch(text::prefix_expression(prefix)))
+        .bind(Param::UInt(limit as u64))
+        .build()?;
+        let rows: Vec<(u64, String, String)> = self.conn()?.query(query)?;
+        Ok(rows
+            .into_iter()
+            .map(|(id, name, cover)| TitleSuggestion { id, name, cover })
+            .collect())
+    }
+
+    /// "Did you mean" for keywords that found nothing,
+    ///     `None` if no word could be corrected or the query is too long.
+    pub fn suggest_keywords(&self, keywords: &str) -> Result<Option<String>, DatabaseError> {
+        let keywords = match suggestible_words(keywords) {
+            None => return Ok(None),
+            Some(keywords) => keywords,
+        };
+        let mut conn = self.conn()?;
+        let mut corrected = false;
+        let mut words: Vec<String> = vec![];
+        for word in keywords {
+            let query = Query::new("CALL QSUGGEST(?, ?, 1 AS limit)")
+                .bind(Param::Str(word.to_owned()))
+                .bind(Param::Str(self.titles_table()))
+                .build()?;
+            let rows: Vec<Row> = conn.query(query)?;
+            match rows.first() {
+                Some(row) => {
+                    let suggestion: String = from_row_column(row, 0)?;
+                    corrected |= suggestion != word.to_lowercase();
+                    words.push(suggestion);
+                }
+                None => words.push(word.to_owned()),
+            };
+        }
+        if !corrected {
+            return Ok(None);
+        }
+        Ok(Some(words.join(" ")))
+    }
+
+    /// Walk every indexed title id in ascending order, `limit` at a time.
+    pub fn title_ids_after(&self, after: u64, limit: u32) -> Result<Vec<u64>, DatabaseError> {
+        let query = Query::new("SELECT id FROM ? WHERE id > ? ORDER BY id ASC LIMIT ?")
+            .bind(Param::Ident(self.titles_table()))
+            .bind(Param::UInt(after))
+            .bind(Param::UInt(limit as u64))
+            .build()?;
+        Ok(self.conn()?.query(query)?)
+    }
+}
+
+/// Words of a query short enough to be corrected, `None` otherwise.
+fn suggestible_words(keywords: &str) -> Option<Vec<&str>> {
+    if keywords.chars().count() > MAX_SUGGEST_LENGTH {
+        return None;
+    }
+    let words: Vec<&str> = keywords.split_whitespace().collect();
+    match words.len() {
+        0 => None,
+        len if len > MAX_SUGGEST_WORDS => None,
+        _ => Some(words),
+    }
+}
+
+/// Alternative spellings of the names, for users typing
+///     without diacritics or in romaji instead of kana.
+fn search_names(document: &TitleDocument) -> Vec<String> {
+    let mut names: Vec<String> = vec![];
+    for name in std::iter::once(&document.name).chain(document.alt_names.iter()) {
+        for variant in text::variants(name).into_iter().skip(1) {
+            if !names.contains(&variant) {
+                names.push(variant);
+            }
+        }
+    }
+    names
+}
+
+fn from_row_column<T: FromValue>(row: &Row, index: usize) -> Result<T, DatabaseError> {
+    match row.get_opt::<T, usize>(index) {
+        Some(Ok(value)) => Ok(value),
+        Some(Err(err)) => Err(DatabaseError::Other(format!("{:?}", err))),
+        None => Err(DatabaseError::Other(format!(
+            "missing column #{} in search result",
+            index
+        ))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(body: Value) -> Result<TitleSearchResult, DatabaseError> {
+        title_search_result(serde_json::from_value(body).unwrap())
+    }
+
+    #[test]
+    fn search_response_to_result() {
+        let result = parse(json!({
+            "took": 1,
+            "timed_out": false,
+            "hits": {
+                "total": 42,
+                "hits": [
+                    {
+                        "_id": 277431062064267264u64,
+                        "_score": 2500,
+                        "_source": { "name": "Yotsuba&!", "cover": "" },
+                        "highlight": { "name": ["<b>Yotsuba</b>&!"] }
+                    },
+                    {
+                        "_id": "12",
+                        "_score": 1200,
+                        "_source": { "name": "Azumanga Daioh" },
+                        "highlight": { "name": [], "alt_names": ["<b>Azumanga</b>", "Daioh"] }
+                    }
+                ]
+            },
+            "aggregations": {
+                "publisher": { "buckets": [{ "key": "Dengeki Daioh", "doc_count": 2 }] },
+                "status": { "buckets": [{ "key": 1, "doc_count": 3 }] }
+            }
+        }))
+        .unwrap();
+        assert_eq!(result.total, 42);
+        assert_eq!(result.hits.len(), 2);
+        assert_eq!(result.hits[0].id, 277431062064267264);
+        assert_eq!(result.hits[0].highlight, "<b>Yotsuba</b>&!");
+        assert_eq!(result.hits[0].weight, 2500);
+        assert_eq!(result.hits[1].id, 12);
+        assert_eq!(result.hits[1].highlight, "<b>Azumanga</b> ... Daioh");
+        assert_eq!(result.publishers[0].value, "Dengeki Daioh");
+        assert_eq!(result.publishers[0].count, 2);
+        assert!(result.formats.is_empty());
+        assert_eq!(result.statuses[0].value, "1");
+    }
+
+    #[test]
+    fn search_response_without_highlight_or_hits() {
+        let result = parse(json!({
+            "hits": { "total": 1, "hits": [{ "_id": 7, "_source": { "name": "Mushishi" } }] }
+        }))
+        .unwrap();
+        assert_eq!(result.hits[0].highlight, "Mushishi");
+        assert_eq!(result.hits[0].weight, 0);
+
+        let result = parse(json!({ "hits": { "total": 0, "hits": [] } })).unwrap();
+        assert_eq!(result.total, 0);
+        assert!(result.hits.is_empty());
+
+        let invalid = parse(json!({ "hits": { "total": 1, "hits": [{ "_id": "x" }] } }));
+        assert!(matches!(invalid, Err(DatabaseError::ManticoreHttpError(_))));
+    }
+
+    #[test]
+    fn only_short_queries_are_corrected() {
+        assert_eq!(
+            suggestible_words("  yotsuba  to "),
+            Some(vec!["yotsuba", "to"])
+        );
+        assert_eq!(suggestible_words("   "), None);
+        assert_eq!(
+            suggestible_words(&"a ".repeat(MAX_SUGGEST_WORDS))
+                .unwrap()
+                .len(),
+            8
+        );
+        assert_eq!(suggestible_words(&"a ".repeat(MAX_SUGGEST_WORDS + 1)), None);
+        assert_eq!(suggestible_words(&"a".repeat(MAX_SUGGEST_LENGTH + 1)), None);
+    }
+}
@@ -0,0 +1,230 @@
+use super::query::escape_match;
+
+/// Vietnamese (and a few other latin) letters with diacritics, grouped by base letter.
+const DIACRITICS: &[(char, &str)] = &[
+    ('a', "àáạảãâầấậẩẫăằắặẳẵä"),
+    ('e', "èéẹẻẽêềếệểễë"),
+    ('i', "ìíịỉĩïî"),
+    ('o', "òóọỏõôồốộổỗơờớợởỡö"),
+    ('u', "ùúụủũưừứựửữüû"),
+    ('y', "ỳýỵỷỹ"),
+    ('d', "đ"),
+];
+
+/// Hepburn romanization of every hiragana, katakana is mapped to hiragana first.
+#[rustfmt::skip]
+const KANA: &[(char, &str)] = &[
+    ('あ', "a"), ('い', "i"), ('う', "u"), ('え', "e"), ('お', "o"),
+    ('か', "ka"), ('き', "ki"), ('く', "ku"), ('け', "ke"), ('こ', "ko"),
+    ('が', "ga"), ('ぎ', "gi"), ('ぐ', "gu"), ('げ', "ge"), ('ご', "go"),
+    ('さ', "sa"), ('し', "shi"), ('す', "su"), ('せ', "se"), ('そ', "so"),
+    ('ざ', "za"), ('じ', "ji"), ('ず', "zu"), ('ぜ', "ze"), ('ぞ', "zo"),
+    ('た', "ta"), ('ち', "chi"), ('つ', "tsu"), ('て', "te"), ('と', "to"),
+    ('だ', "da"), ('ぢ', "ji"), ('づ', "zu"), ('で', "de"), ('ど', "do"),
+    ('な', "na"), ('に', "ni"), ('ぬ', "nu"), ('ね', "ne"), ('の', "no"),
+    ('は', "ha"), ('ひ', "hi"), ('ふ', "fu"), ('へ', "he"), ('ほ', "ho"),
+    ('ば', "ba"), ('び', "bi"), ('ぶ', "bu"), ('べ', "be"), ('ぼ', "bo"),
+    ('ぱ', "pa"), ('ぴ', "pi"), ('ぷ', "pu"), ('ぺ', "pe"), ('ぽ', "po"),
+    ('ま', "ma"), ('み', "mi"), ('む', "mu"), ('め', "me"), ('も', "mo"),
+    ('や', "ya"), ('ゆ', "yu"), ('よ', "yo"),
+    ('ら', "ra"), ('り', "ri"), ('る', "ru"), ('れ', "re"), ('ろ', "ro"),
+    ('わ', "wa"), ('ゐ', "i"), ('ゑ', "e"), ('を', "o"), ('ん', "n"), ('ゔ', "vu"),
+    ('ぁ', "a"), ('ぃ', "i"), ('ぅ', "u"), ('ぇ', "e"), ('ぉ', "o"), ('ゎ', "wa"),
+];
+
+/// Small kana that merge into the previous one (きゃ -> kya, ファ -> fa).
+#[rustfmt::skip]
+const SMALL_KANA: &[(char, char)] = &[
+    ('ゃ', 'a'), ('ゅ', 'u'), ('ょ', 'o'),
+    ('ぁ', 'a'), ('ぃ', 'i'), ('ぅ', 'u'), ('ぇ', 'e'), ('ぉ', 'o'),
+];
+
+/// Remove diacritics, so `Thám tử lừng danh` can be found by `tham tu lung danh`.
+pub fn fold_diacritics(text: &str) -> String {
+    text.chars()
+        .map(|c| {
+            let lower = c.to_lowercase().next().unwrap_or(c);
+            match DIACRITICS.iter().find(|(_, group)| group.contains(lower)) {
+                None => c,
+                Some((base, _)) if c.is_uppercase() => base.to_ascii_uppercase(),
+                Some((base, _)) => *base,
+            }
+        })
+        .collect()
+}
+
+/// Katakana to hiragana, both share the same layout.
+fn to_hiragana(c: char) -> char {
+    match c {
+        'ァ'..='ヶ' => char::from_u32(c as u32 - 0x60).unwrap_or(c),
+        _ => c,
+    }
+}
+
+fn romaji_of(c: char) -> Option<&'static str> {
+    KANA.iter()
+        .find(|(kana, _)| *kana == c)
+        .map(|(_, romaji)| *romaji)
+}
+
+/// Transliterate kana into romaji, anything else is kept as is.
+pub fn kana_to_romaji(text: &str) -> String {
+    let chars: Vec<char> = text.chars().map(to_hiragana).collect();
+    let mut romaji = String::with_capacity(text.len());
+    let mut double_next = false;
+    let mut i = 0;
+    while i < chars.len() {
+        let c = chars[i];
+        if c == 'っ' {
+            double_next = true;
+            i += 1;
+            continue;
+        }
+        if c == 'ー' {
+            if let Some(vowel) = romaji.chars().last().filter(|c| "aeiou".contains(*c)) {
+                romaji.push(vowel);
+            }
+            i += 1;
+            continue;
+        }
+        let mut syllable = match romaji_of(c) {
+            None => {
+                romaji.push(c);
+                double_next = false;
+                i += 1;
+                continue;
+            }
+            Some(syllable) => syllable.to_owned(),
+        };
+        let small = chars.get(i + 1).and_then(|next| {
+            SMALL_KANA
+                .iter()
+                .find(|(kana, _)| kana == next)
+                .map(|(kana, vowel)| (*kana, *vowel))
+        });
+        if let Some((kana, vowel)) = small {
+            if syllable.len() > 1 {
+                syllable.pop();
+                let is_yoon = matches!(kana, 'ゃ' | 'ゅ' | 'ょ');
+                if is_yoon && !matches!(syllable.as_str(), "sh" | "ch" | "j") {
+                    syllable.push('y');
+                }
+            }
+            syllable.push(vowel);
+            i += 1;
+        }
+        if double_next {
+            match syllable.chars().next() {
+                Some('c') => romaji.push('t'),
+                Some(consonant) if !"aeiou".contains(consonant) => romaji.push(consonant),
+                _ => {}
+            };
+            double_next = false;
+        }
+        romaji.push_str(&syllable);
+        i += 1;
+    }
+    romaji
+}
+
+/// Spellings of the same text that are worth indexing or searching,
+///     the original one is always first.
+pub fn variants(text: &str) -> Vec<String> {
+    let mut variants = vec![text.to_owned()];
+    for variant in [fold_diacritics(text), kana_to_romaji(text)] {
+        if !variants.contains(&variant) {
+            variants.push(variant);
+        }
+    }
+    variants
+}
+
+/// Build a `MATCH()` expression that accept any spelling of the user's keywords.
+/// Every variant is escaped, so the user still can't inject operators.
+pub fn search_expression(keywords: &str) -> String {
+    variants(keywords.trim())
+        .iter()
+        .map(|variant| format!("({})", escape_match(variant)))
+        .collect::<Vec<String>>()
+        .join(" | ")
+}
+
+/// Build a `MATCH()` expression for search-as-you-type over the names,
+///     the last (probably unfinished) word is matched as a prefix.
+pub fn prefix_expression(prefix: &str) -> String {
+    let alternatives: Vec<String> = variants(prefix.trim())
+        .iter()
+        .map(|variant| {
+            let words: Vec<String> = variant.split_whitespace().map(escape_match).collect();
+            format!("({}*)", words.join(" "))
+        })
+        .collect();
+    format!(
+        "@(name,alt_names,search_names) {}",
+        alternatives.join(" | ")
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn fold_vietnamese() {
+        assert_eq!(fold_diacritics("Thám tử lừng danh"), "Tham tu lung danh");
+        assert_eq!(fold_diacritics("Đội quân Đô-rê-mon"), "Doi quan Do-re-mon");
+        assert_eq!(fold_diacritics("plain text"), "plain text");
+    }
+
+    #[test]
+    fn romaji_basic() {
+        assert_eq!(kana_to_romaji("ひらがな"), "hiragana");
+        assert_eq!(kana_to_romaji("カタカナ"), "katakana");
+        assert_eq!(kana_to_romaji("しんかい"), "shinkai");
+    }
+
+    #[test]
+    fn romaji_digraphs() {
+        assert_eq!(kana_to_romaji("きょう"), "kyou");
+        assert_eq!(kana_to_romaji("しゃしん"), "shashin");
+        assert_eq!(kana_to_romaji("ちゃ"), "cha");
+        assert_eq!(kana_to_romaji("ファン"), "fan");
+    }
+
+    #[test]
+    fn romaji_double_consonant_and_long_vowel() {
+        assert_eq!(kana_to_romaji("がっこう"), "gakkou");
+        assert_eq!(kana_to_romaji("まっちゃ"), "matcha");
+        assert_eq!(kana_to_romaji("スーパー"), "suupaa");
+    }
+
+    #[test]
+    fn romaji_keep_other_characters() {
+        assert_eq!(kana_to_romaji("推しの子"), "推shino子");
+        assert_eq!(kana_to_romaji("Oshi no Ko"), "Oshi no Ko");
+    }
+
+    #[test]
+    fn search_expression_variants() {
+        assert_eq!(search_expression("oshi no ko"), "(oshi no ko)");
+        assert_eq!(search_expression("thám tử"), "(thám tử) | (tham tu)");
+        assert_eq!(search_expression("かぐや"), "(かぐや) | (kaguya)");
+        assert_eq!(search_expression("-x"), "(\\-x)");
+    }
+
+    #[test]
+    fn prefix_expression_variants() {
+        assert_eq!(
+            prefix_expression(" oshi no "),
+            "@(name,alt_names,search_names) (oshi no*)"
+        );
+        assert_eq!(
+            prefix_expression("thám t"),
+            "@(name,alt_names,search_names) (thám t*) | (tham t*)"
+        );
+        assert_eq!(
+            prefix_expression("a*"),
+            "@(name,alt_names,search_names) (a\\**)"
+        );
+    }
+}
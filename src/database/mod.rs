@@ -1,6 +1,8 @@
 pub mod bundle;
 pub mod cache;
 pub mod error;
+pub mod indexer;
+pub mod jobs;
 pub mod manticore;
 pub mod redis;
 pub mod scylla;
@@ -1,46 +1,336 @@
-use super::cache::{CacheError, CacheModule};
+use super::cache::CacheError;
 use super::error::DatabaseError;
-use crate::server_config::redis::RedisConfig;
-use r2d2::{Error, Pool, PooledConnection};
-use r2d2_redis::{r2d2, redis, RedisConnectionManager};
-use std::{convert::Into, ops::DerefMut, sync::Arc};
+use crate::server_config::redis::{RedisConfig, RedisMode};
+use redis::{aio::PubSub, ErrorKind, FromRedisValue, RedisError, RedisResult};
+use std::{
+    future::Future,
+    io,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        RwLock,
+    },
+    time::Duration,
+};
 
+pub mod stream;
+pub mod topology;
+
+use topology::{Conn, Topology};
+
+/// Multiplexed connections to redis, each one serving many concurrent commands.
+/// A dropped connection is reopened on the next command that uses it.
 pub struct RedisWrapper {
-    pub pool: Arc<Pool<RedisConnectionManager>>,
+    config: RedisConfig,
+    /// Replaced when sentinel reports a new master.
+    topology: RwLock<Topology>,
+    /// Only one caller looks for the new master at a time.
+    failover: tokio::sync::Mutex<()>,
+    /// Round-robin over the connections.
+    next: AtomicUsize,
+    connect_timeout: Duration,
+    response_timeout: Duration,
+}
+
+fn timed_out(what: &str) -> CacheError {
+    CacheError::from(RedisError::from(io::Error::new(
+        io::ErrorKind::TimedOut,
+        format!("redis {} timed out", what),
+    )))
 }
 
 impl RedisWrapper {
-    pub async fn new(config: &RedisConfig) -> Result<Self, Error> {
-        let manager = RedisConnectionManager::new(config.uri.to_owned()).unwrap();
-        let pool = r2d2::Pool::builder().build(manager).unwrap();
+    pub async fn new(config: &RedisConfig) -> Result<Self, DatabaseError> {
+        let connect_timeout = Duration::from_millis(config.connect_timeout);
+        let topology = Topology::connect(config, connect_timeout).await?;
+        if let Some(master) = &topology.master {
+            log::info!(
+                "[redis] master {:?} is at {}",
+                config.sentinel_master,
+                master
+            );
+        }
         Ok(RedisWrapper {
-            pool: Arc::new(pool),
+            config: config.clone(),
+            topology: RwLock::new(topology),
+            failover: tokio::sync::Mutex::new(()),
+            next: AtomicUsize::new(0),
+            connect_timeout,
+            response_timeout: Duration::from_millis(config.response_timeout),
         })
     }
 
-    pub fn conn(&self) -> Result<PooledConnection<RedisConnectionManager>, DatabaseError> {
-        let pool = self.pool.clone();
-        Ok(pool.get()?)
+    /// A handle on one of the connections, cheap to clone.
+    fn conn(&self) -> Conn {
+        let next = self.next.fetch_add(1, Ordering::Relaxed);
+        let topology = self.topology.read().unwrap_or_else(|err| err.into_inner());
+        topology.connections[next % topology.connections.len()].clone()
+    }
+
+    /// Wait for a reply, for at most `response_timeout`.
+    async fn timed<T>(
+        &self,
+        reply: impl Future<Output = RedisResult<T>>,
+    ) -> Result<T, DatabaseError> {
+        match tokio::time::timeout(self.response_timeout, reply).await {
+            Err(_) => {
+                self.follow_master().await;
+                Err(timed_out("response").into())
+            }
+            Ok(Err(err)) => {
+                if err.is_io_error()
+                    || err.is_connection_dropped()
+                    || err.kind() == ErrorKind::ReadOnly
+                {
+                    self.follow_master().await;
+                }
+                Err(CacheError::from(err).into())
+            }
+            Ok(Ok(reply)) => Ok(reply),
+        }
+    }
+
+    /// After a failover the old master is gone or refuses writes,
+    ///     ask sentinel where the new one is and switch to it.
+    async fn follow_master(&self) {
+        if self.config.mode != RedisMode::Sentinel {
+            return;
+        }
+        let _guard = match self.failover.try_lock() {
+            // someone else is already on it.
+            Err(_) => return,
+            Ok(guard) => guard,
+        };
+        let info = match topology::discover_master(&self.config, self.connect_timeout).await {
+            Err(err) => {
+                log::warn!("[redis] failed to look for the master: {:?}", err);
+                return;
+            }
+            Ok(info) => info,
+        };
+        let master = info.addr.to_string();
+        let current = self
+            .topology
+            .read()
+            .unwrap_or_else(|err| err.into_inner())
+            .master
+            .clone();
+        if current.as_ref() == Some(&master) {
+            return;
+        }
+        match Topology::single(
+            info,
+            self.config.pool_size,
+            self.connect_timeout,
+            Some(master.clone()),
+        )
+        .await
+        {
+            Err(err) => log::warn!("[redis] failed to connect to the new master: {:?}", err),
+            Ok(topology) => {
+                log::warn!("[redis] master moved to {}", master);
+                *self.topology.write().unwrap_or_else(|err| err.into_inner()) = topology;
+            }
+        };
+    }
+
+    async fn query<T: FromRedisValue>(&self, cmd: &redis::Cmd) -> Result<T, DatabaseError> {
+        let mut conn = self.conn();
+        self.timed(cmd.query_async::<_, T>(&mut conn)).await
+    }
+
+    /// A dedicated connection in subscriber mode, it can't be multiplexed.
+    pub async fn pubsub(&self) -> Result<PubSub, DatabaseError> {
+        let client = self
+            .topology
+            .read()
+            .unwrap_or_else(|err| err.into_inner())
+            .client
+            .clone();
+        let conn = self.timed(client.get_async_connection()).await?;
+        Ok(conn.into_pubsub())
     }
-}
 
-impl CacheModule for RedisWrapper {
-    fn set<K: Into<String>>(
+    /// Set `key` to `value`, expiring after `expire` miliseconds.
+    pub async fn set<K: Into<String>>(
         &self,
         key: K,
         value: Vec<u8>,
         expire: usize,
     ) -> Result<(), DatabaseError> {
-        let mut conn = self.conn()?;
-        redis::Cmd::pset_ex(Into::<String>::into(key), value, expire).execute(conn.deref_mut());
+        self.query(&redis::Cmd::pset_ex(key.into(), value, expire))
+            .await
+    }
+
+    /// `None` if the key doesn't exist (or has expired).
+    pub async fn get<K: Into<String>>(&self, key: K) -> Result<Option<Vec<u8>>, DatabaseError> {
+        self.query(&redis::Cmd::get(key.into())).await
+    }
+
+    /// Push a value at the head of a list (used as a queue).
+    pub async fn push(&self, queue: &str, value: Vec<u8>) -> Result<(), DatabaseError> {
+        self.query(redis::cmd("LPUSH").arg(queue).arg(value)).await
+    }
+
+    /// Move the oldest value of `queue` into `processing` and return it,
+    ///     so it is not lost if we crash while handling it.
+    pub async fn claim(
+        &self,
+        queue: &str,
+        processing: &str,
+    ) -> Result<Option<Vec<u8>>, DatabaseError> {
+        self.query(redis::cmd("RPOPLPUSH").arg(queue).arg(processing))
+            .await
+    }
+
+    /// Remove a claimed value from `processing` once it has been handled.
+    pub async fn ack(&self, processing: &str, value: &[u8]) -> Result<(), DatabaseError> {
+        self.query(redis::cmd("LREM").arg(processing).arg(1).arg(value))
+            .await
+    }
+
+    /// Move every value left in `processing` back to `queue`,
+    ///     return how many have been moved.
+    pub async fn requeue(&self, processing: &str, queue: &str) -> Result<usize, DatabaseError> {
+        let mut count = 0;
+        while self.claim(processing, queue).await?.is_some() {
+            count += 1;
+        }
+        Ok(count)
+    }
+
+    /// Take a lock for `expire` miliseconds, unless someone else holds it.
+    /// `token` must be unique to the holder, it's needed to release the lock.
+    pub async fn try_lock(
+        &self,
+        key: &str,
+        token: &str,
+        expire: usize,
+    ) -> Result<bool, DatabaseError> {
+        let reply: Option<String> = self
+            .query(
+                redis::cmd("SET")
+                    .arg(key)
+                    .arg(token)
+                    .arg("NX")
+                    .arg("PX")
+                    .arg(expire),
+            )
+            .await?;
+        Ok(reply.is_some())
+    }
+
+    /// Release a lock, only if it's still ours (it may have expired and been taken since).
+    pub async fn unlock(&self, key: &str, token: &str) -> Result<(), DatabaseError> {
+        const UNLOCK_SCRIPT: &str = r#"
+            if redis.call("GET", KEYS[1]) == ARGV[1] then
+                return redis.call("DEL", KEYS[1])
+            end
+            return 0
+        "#;
+        let mut conn = self.conn();
+        let script = redis::Script::new(UNLOCK_SCRIPT);
+        let mut invocation = script.key(key);
+        invocation.arg(token);
+        self.timed(invocation.invoke_async::<_, i64>(&mut conn))
+            .await?;
         Ok(())
     }
 
-    fn get<K: Into<String>>(&self, key: K) -> Result<Vec<u8>, DatabaseError> {
-        let mut conn = self.conn()?;
-        match redis::Cmd::get(Into::<String>::into(key)).query(conn.deref_mut()) {
-            Err(err) => Err(DatabaseError::CacheError(Into::<CacheError>::into(err))),
-            Ok(cbor) => Ok(cbor),
+    /// Count one request against the budget of `key`: `burst` requests at once,
+    ///     then one every `interval` miliseconds (GCRA, a token bucket in a single key).
+    /// Return 0 if allowed, otherwise how long to wait (in miliseconds).
+    /// Redis' clock is used, so replicas agree whatever their own clock says.
+    pub async fn throttle(
+        &self,
+        key: &str,
+        interval: u64,
+        burst: u64,
+    ) -> Result<u64, DatabaseError> {
+        const THROTTLE_SCRIPT: &str = r#"
+            local time = redis.call("TIME")
+            local now = tonumber(time[1]) * 1000 + math.floor(tonumber(time[2]) / 1000)
+            local interval = tonumber(ARGV[1])
+            local burst = tonumber(ARGV[2])
+            local tat = math.max(tonumber(redis.call("GET", KEYS[1])) or now, now)
+            local allowed_at = tat + interval - interval * burst
+            if now < allowed_at then
+                return allowed_at - now
+            end
+            redis.call("SET", KEYS[1], tat + interval, "PX", tat + interval - now)
+            return 0
+        "#;
+        let mut conn = self.conn();
+        let script = redis::Script::new(THROTTLE_SCRIPT);
+        let mut invocation = script.key(key);
+        invocation.arg(interval).arg(burst);
+        self.timed(invocation.invoke_async::<_, u64>(&mut conn))
+            .await
+    }
+
+    /// Add members to a set, and (re)set its expiration in miliseconds.
+    pub async fn add_to_set(
+        &self,
+        key: &str,
+        members: &[String],
+        expire: usize,
+    ) -> Result<(), DatabaseError> {
+        if members.is_empty() {
+            return Ok(());
         }
+        let mut conn = self.conn();
+        let mut pipe = redis::pipe();
+        pipe.atomic()
+            .cmd("SADD")
+            .arg(key)
+            .arg(members)
+            .ignore()
+            .cmd("PEXPIRE")
+            .arg(key)
+            .arg(expire)
+            .ignore();
+        self.timed(pipe.query_async::<_, ()>(&mut conn)).await
+    }
+
+    pub async fn set_members(&self, key: &str) -> Result<Vec<String>, DatabaseError> {
+        self.query(redis::cmd("SMEMBERS").arg(key)).await
+    }
+
+    pub async fn remove_from_set(
+        &self,
+        key: &str,
+        members: &[String],
+    ) -> Result<(), DatabaseError> {
+        if members.is_empty() {
+            return Ok(());
+        }
+        self.query(redis::cmd("SREM").arg(key).arg(members)).await
+    }
+
+    pub async fn delete(&self, keys: &[String]) -> Result<(), DatabaseError> {
+        if keys.is_empty() {
+            return Ok(());
+        }
+        if self.config.mode == RedisMode::Cluster {
+            // the keys may live on different nodes, which a single `DEL` can't span.
+            let deletes: Vec<redis::Cmd> = keys.iter().map(|key| redis::Cmd::del(key)).collect();
+            futures::future::try_join_all(deletes.iter().map(|cmd| self.query::<()>(cmd))).await?;
+            return Ok(());
+        }
+        self.query(redis::cmd("DEL").arg(keys)).await
+    }
+
+    /// Current value of a counter, 0 if it doesn't exist.
+    pub async fn counter(&self, key: &str) -> Result<u64, DatabaseError> {
+        let value: Option<u64> = self.query(redis::cmd("GET").arg(key)).await?;
+        Ok(value.unwrap_or(0))
+    }
+
+    pub async fn increment(&self, key: &str) -> Result<u64, DatabaseError> {
+        self.query(redis::cmd("INCR").arg(key)).await
+    }
+
+    pub async fn publish(&self, channel: &str, payload: Vec<u8>) -> Result<(), DatabaseError> {
+        self.query(redis::cmd("PUBLISH").arg(channel).arg(payload))
+            .await
     }
 }
@@ -0,0 +1,246 @@
+use super::RedisWrapper;
+use crate::database::{cache::CacheError, error::DatabaseError};
+use redis::streams::{
+    StreamClaimReply, StreamId, StreamPendingCountReply, StreamPendingReply, StreamReadReply,
+};
+
+impl RedisWrapper {
+    /// Append an entry holding a single `field`, return its id.
+    /// The stream is trimmed to roughly `max_len` entries.
+    pub async fn stream_add(
+        &self,
+        stream: &str,
+        max_len: usize,
+        field: &str,
+        value: Vec<u8>,
+    ) -> Result<String, DatabaseError> {
+        self.query(
+            redis::cmd("XADD")
+                .arg(stream)
+                .arg("MAXLEN")
+                .arg("~")
+                .arg(max_len)
+                .arg("*")
+                .arg(field)
+                .arg(value),
+        )
+        .await
+    }
+
+    /// Create a consumer group reading `stream` from the start (and the stream
+    ///     if needed), unless it already exists.
+    pub async fn stream_group(&self, stream: &str, group: &str) -> Result<(), DatabaseError> {
+        let result = self
+            .query::<()>(
+                redis::cmd("XGROUP")
+                    .arg("CREATE")
+                    .arg(stream)
+                    .arg(group)
+                    .arg("0")
+                    .arg("MKSTREAM"),
+            )
+            .await;
+        match result {
+            Err(DatabaseError::CacheError(CacheError::RedisError(err)))
+                if err.code() == Some("BUSYGROUP") =>
+            {
+                Ok(())
+            }
+            result => result,
+        }
+    }
+
+    /// Up to `count` entries never delivered to `group`, now pending for `consumer`.
+    /// Doesn't block: `BLOCK` would hold up the multiplexed connection.
+    pub async fn stream_read(
+        &self,
+        stream: &str,
+        group: &str,
+        consumer: &str,
+        count: usize,
+    ) -> Result<Vec<StreamId>, DatabaseError> {
+        let reply: Option<StreamReadReply> = self
+            .query(
+                redis::cmd("XREADGROUP")
+                    .arg("GROUP")
+                    .arg(group)
+                    .arg(consumer)
+                    .arg("COUNT")
+                    .arg(count)
+                    .arg("STREAMS")
+                    .arg(stream)
+                    .arg(">"),
+            )
+            .await?;
+        Ok(reply
+            .map(|reply| reply.keys.into_iter().flat_map(|key| key.ids).collect())
+            .unwrap_or_default())
+    }
+
+    /// Acknowledge entries handled by `group`, and drop them from the stream.
+    pub async fn stream_ack(
+        &self,
+        stream: &str,
+        group: &str,
+        ids: &[String],
+    ) -> Result<(), DatabaseError> {
+        if ids.is_empty() {
+            return Ok(());
+        }
+        let mut conn = self.conn();
+        let mut pipe = redis::pipe();
+        pipe.atomic()
+            .cmd("XACK")
+            .arg(stream)
+            .arg(group)
+            .arg(ids)
+            .ignore()
+            .cmd("XDEL")
+            .arg(stream)
+            .arg(ids)
+            .ignore();
+        self.timed(pipe.query_async::<_, ()>(&mut conn)).await
+    }
+
+    /// How many entries are pending (delivered but not acknowledged), per consumer.
+    pub async fn stream_pending_summary(
+        &self,
+        stream: &str,
+        group: &str,
+    ) -> Result<StreamPendingReply, DatabaseError> {
+        self.query(redis::cmd("XPENDING").arg(stream).arg(group))
+            .await
+    }
+
+    /// The oldest `count` pending entries from `start` (`-` for the first one,
+    ///     `(<id>` for the ones after `id`) idle for at least `min_idle` miliseconds,
+    ///     with their consumer, idle time and deliveries.
+    pub async fn stream_pending(
+        &self,
+        stream: &str,
+        group: &str,
+        min_idle: usize,
+        start: &str,
+        count: usize,
+    ) -> Result<StreamPendingCountReply, DatabaseError> {
+        let mut cmd = redis::cmd("XPENDING");
+        cmd.arg(stream).arg(group);
+        if min_idle > 0 {
+            cmd.arg("IDLE").arg(min_idle);
+        }
+        cmd.arg(start).arg("+").arg(count);
+        self.query(&cmd).await
+    }
+
+    /// Take over pending entries idle for at least `min_idle` miliseconds,
+    ///     return the ones we got (another consumer may have been faster).
+    pub async fn stream_claim(
+        &self,
+        stream: &str,
+        group: &str,
+        consumer: &str,
+        min_idle: usize,
+        ids: &[String],
+    ) -> Result<Vec<StreamId>, DatabaseError> {
+        if ids.is_empty() {
+            return Ok(vec![]);
+        }
+        let reply: StreamClaimReply = self
+            .query(
+                redis::cmd("XCLAIM")
+                    .arg(stream)
+                    .arg(group)
+                    .arg(consumer)
+                    .arg(min_idle)
+                    .arg(ids),
+            )
+            .await?;
+        Ok(reply.ids)
+    }
+
+    /// Reset the idle time of entries pending for `consumer`, so they aren't
+    ///     claimed by someone else (`JUSTID` leaves their delivery count alone).
+    pub async fn stream_touch(
+        &self,
+        stream: &str,
+        group: &str,
+        consumer: &str,
+        ids: &[String],
+    ) -> Result<(), DatabaseError> {
+        if ids.is_empty() {
+            return Ok(());
+        }
+        let _: Vec<String> = self
+            .query(
+                redis::cmd("XCLAIM")
+                    .arg(stream)
+                    .arg(group)
+                    .arg(consumer)
+                    .arg(0)
+                    .arg(ids)
+                    .arg("JUSTID"),
+            )
+            .await?;
+        Ok(())
+    }
+
+    pub async fn stream_len(&self, stream: &str) -> Result<u64, DatabaseError> {
+        self.query(redis::cmd("XLEN").arg(stream)).await
+    }
+
+    /// Hold `value` in the sorted set `delayed` for `delay` miliseconds (by redis' clock),
+    ///     see `release_delayed`.
+    pub async fn delay(
+        &self,
+        delayed: &str,
+        delay: u64,
+        value: Vec<u8>,
+    ) -> Result<(), DatabaseError> {
+        const DELAY_SCRIPT: &str = r#"
+            local time = redis.call("TIME")
+            local now = tonumber(time[1]) * 1000 + math.floor(tonumber(time[2]) / 1000)
+            redis.call("ZADD", KEYS[1], now + tonumber(ARGV[1]), ARGV[2])
+            return 0
+        "#;
+        let mut conn = self.conn();
+        let script = redis::Script::new(DELAY_SCRIPT);
+        let mut invocation = script.key(delayed);
+        invocation.arg(delay).arg(value);
+        self.timed(invocation.invoke_async::<_, i64>(&mut conn))
+            .await?;
+        Ok(())
+    }
+
+    /// Move up to `count` values of `delayed` that are due into `stream` (as `field`),
+    ///     return how many have been moved.
+    /// Both keys must be on the same cluster node (i.e. share a hash tag).
+    pub async fn release_delayed(
+        &self,
+        delayed: &str,
+        stream: &str,
+        max_len: usize,
+        field: &str,
+        count: usize,
+    ) -> Result<u64, DatabaseError> {
+        const RELEASE_SCRIPT: &str = r#"
+            local time = redis.call("TIME")
+            local now = tonumber(time[1]) * 1000 + math.floor(tonumber(time[2]) / 1000)
+            local due = redis.call("ZRANGEBYSCORE", KEYS[1], "-inf", now, "LIMIT", 0, ARGV[1])
+            for _, value in ipairs(due) do
+                redis.call("ZREM", KEYS[1], value)
+                redis.call("XADD", KEYS[2], "MAXLEN", "~", ARGV[2], "*", ARGV[3], value)
+            end
+            return #due
+        "#;
+        let mut conn = self.conn();
+        let script = redis::Script::new(RELEASE_SCRIPT);
+        let mut invocation = script.key(delayed);
+        invocation.key(stream).arg(count).arg(max_len).arg(field);
+        self.timed(invocation.invoke_async::<_, u64>(&mut conn))
+            .await
+    }
+
+    pub async fn sorted_set_len(&self, key: &str) -> Result<u64, DatabaseError> {
+        self.query(redis::cmd("ZCARD").arg(key)).await
+    }
+}
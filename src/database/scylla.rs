@@ -3,19 +3,68 @@ use super::{
     sync::{SyncResponse, SyncSupport},
 };
 use crate::server_config::scylla::ScyllaConfig;
-use scylla::{transport::errors, IntoTypedRows, Session, SessionBuilder};
-use std::result::Result;
+use bytes::Bytes;
+use scylla::{
+    cql_to_rust::FromRow,
+    frame::value::ValueList,
+    query::Query,
+    retry_policy::DefaultRetryPolicy,
+    speculative_execution::SimpleSpeculativeExecutionPolicy,
+    transport::{errors, ExecutionProfile},
+    IntoTypedRows, Session, SessionBuilder,
+};
+use std::{result::Result, sync::Arc, time::Duration};
 
+pub mod batch;
+pub mod introspect;
+pub mod retry;
 pub mod schema;
 
+use retry::RetryPolicy;
+
 pub struct ScyllaWrapper {
     pub session: Session,
+    pub keyspace: String,
+    pub retry: RetryPolicy,
+}
+
+/// A single page of a paged query,
+///     along with the paging state needed to fetch the next one.
+pub struct ScyllaPage<T> {
+    pub rows: Vec<T>,
+    pub paging_state: Option<Vec<u8>>,
+}
+
+impl<T> ScyllaPage<T> {
+    /// Convert the rows, keeping the paging state.
+    pub fn map<U>(self, f: impl FnMut(T) -> U) -> ScyllaPage<U> {
+        ScyllaPage {
+            rows: self.rows.into_iter().map(f).collect(),
+            paging_state: self.paging_state,
+        }
+    }
 }
 
 impl ScyllaWrapper {
     pub async fn new(config: &ScyllaConfig) -> Result<Self, DatabaseError> {
+        // speculative execution only ever kicks in for statements marked as idempotent,
+        // and the default retry policy won't retry a non-idempotent one on ambiguous errors.
+        let speculative_policy = match config.speculative_max {
+            0 => None,
+            max_retry_count => Some(Arc::new(SimpleSpeculativeExecutionPolicy {
+                max_retry_count,
+                retry_interval: Duration::from_millis(config.speculative_delay),
+            }) as _),
+        };
+        let profile = ExecutionProfile::builder()
+            .request_timeout(Some(Duration::from_millis(config.request_timeout)))
+            .retry_policy(Box::new(DefaultRetryPolicy::new()))
+            .speculative_execution_policy(speculative_policy)
+            .build();
         let builder: SessionBuilder = SessionBuilder::new()
             .known_node(&config.uri)
+            .connection_timeout(Duration::from_millis(config.connect_timeout))
+            .default_execution_profile_handle(profile.into_handle())
             .use_keyspace(&config.keyspace, true);
         let builder = if config.had_auth() {
             builder.user(&config.user, &config.password)
@@ -24,8 +73,45 @@ impl ScyllaWrapper {
         };
         Ok(Self {
             session: builder.build().await?,
+            keyspace: config.keyspace.to_owned(),
+            retry: RetryPolicy::new(config),
         })
     }
+
+    /// Build an idempotent query, which the driver is allowed
+    ///     to retry and execute speculatively.
+    pub fn idempotent(query: &str) -> Query {
+        let mut query = Query::new(query);
+        query.set_is_idempotent(true);
+        query
+    }
+
+    /// Fetch at most `page_size` rows, starting from `paging_state`
+    ///     (or from the beginning if there is none).
+    pub async fn query_page<T: FromRow>(
+        &self,
+        query: &str,
+        values: impl ValueList,
+        page_size: i32,
+        paging_state: Option<Vec<u8>>,
+    ) -> Result<ScyllaPage<T>, DatabaseError> {
+        let query = Self::idempotent(query).with_page_size(page_size);
+        let values = values.serialized()?.into_owned();
+        let paging_state = paging_state.map(Bytes::from);
+        let result = self
+            .retry
+            .run(|| {
+                self.session
+                    .query_paged(query.clone(), &values, paging_state.clone())
+            })
+            .await?;
+        let paging_state = result.paging_state.as_ref().map(|state| state.to_vec());
+        let rows = match result.rows {
+            None => vec![],
+            Some(rows) => rows.into_typed::<T>().collect::<Result<Vec<T>, _>>()?,
+        };
+        Ok(ScyllaPage { rows, paging_state })
+    }
 }
 
 impl SyncSupport for ScyllaWrapper {
@@ -36,9 +122,12 @@ impl SyncSupport for ScyllaWrapper {
     fn schema_version(&self) -> Result<Option<i64>, DatabaseError> {
         const VERSION_FIELD: &str = "schema_version";
         const VERSION_QUERY: &str = "SELECT value FROM sync_data WHERE field = ?;";
+        let query = Self::idempotent(VERSION_QUERY);
         let query = tokio::task::block_in_place(move || {
             tokio::runtime::Handle::current().block_on(async move {
-                self.session.query(VERSION_QUERY, vec![VERSION_FIELD]).await
+                self.retry
+                    .run(|| self.session.query(query.clone(), vec![VERSION_FIELD]))
+                    .await
             })
         });
         let query = match query {
@@ -73,10 +162,15 @@ impl SyncSupport for ScyllaWrapper {
     fn set_schema_version(&self, version: i64) -> SyncResponse {
         const VERSION_FIELD: &str = "schema_version";
         const VERSION_QUERY: &str = "UPDATE sync_data SET value = ? WHERE field = ?;";
+        let query = Self::idempotent(VERSION_QUERY);
+        let version = format!("{}", version);
         let _ = tokio::task::block_in_place(move || {
             tokio::runtime::Handle::current().block_on(async move {
-                self.session
-                    .query(VERSION_QUERY, (format!("{}", version), VERSION_FIELD))
+                self.retry
+                    .run(|| {
+                        self.session
+                            .query(query.clone(), (version.as_str(), VERSION_FIELD))
+                    })
                     .await
             })
         })?;
@@ -0,0 +1,258 @@
+use super::ScyllaWrapper;
+use crate::database::error::DatabaseError;
+use scylla::{
+    batch::{Batch, BatchType},
+    frame::value::{SerializedResult, SerializedValues, ValueList},
+    query::Query,
+    transport::errors::QueryError,
+};
+use std::ops::Range;
+
+/// Maximum number of statements in a single batch.
+const DEFAULT_MAX_STATEMENTS: usize = 50;
+
+/// Maximum size of a single batch, scylla start warning
+///     at 5kb (`batch_size_warn_threshold_in_kb`) and reject it at 50kb.
+const DEFAULT_MAX_BYTES: usize = 5 * 1024;
+
+/// A typed write, usually one row of a denormalized view.
+pub trait Statement {
+    fn query(&self) -> &'static str;
+    fn values(&self) -> SerializedResult<'_>;
+}
+
+/// Collect statements then write them with as few batches as possible.
+/// A logged batch that exceed the limits is refused, unless splitting has been
+///     allowed with `split`: each batch is then still atomic, but the whole write is not.
+/// Batches are idempotent (thus retried) by default,
+///     turn it off for counter updates or list appends.
+pub struct BatchWriter<'a> {
+    scylla: &'a ScyllaWrapper,
+    batch_type: BatchType,
+    max_statements: usize,
+    max_bytes: usize,
+    split: bool,
+    idempotent: bool,
+    statements: Vec<(Query, SerializedValues)>,
+}
+
+/// One of the splitted batch that failed.
+#[allow(dead_code)]
+#[derive(Debug, Clone)]
+pub struct BatchFailure {
+    /// Index of the statements in this batch, in insertion order.
+    pub statements: Range<usize>,
+    pub error: QueryError,
+}
+
+/// Result of a batched write.
+#[allow(dead_code)]
+#[derive(Debug, Clone, Default)]
+pub struct BatchReport {
+    /// Number of statements that have been applied.
+    pub applied: usize,
+    pub failures: Vec<BatchFailure>,
+}
+
+#[allow(dead_code)]
+impl BatchReport {
+    pub fn is_success(&self) -> bool {
+        self.failures.is_empty()
+    }
+}
+
+// no denormalized view is written yet.
+#[allow(dead_code)]
+impl ScyllaWrapper {
+    /// Start a logged batch, use this when all the views must be updated together.
+    pub fn logged_batch(&self) -> BatchWriter<'_> {
+        BatchWriter::new(self, BatchType::Logged)
+    }
+
+    /// Start an unlogged batch, for writes that only target a single partition.
+    pub fn unlogged_batch(&self) -> BatchWriter<'_> {
+        BatchWriter::new(self, BatchType::Unlogged)
+    }
+}
+
+#[allow(dead_code)]
+impl<'a> BatchWriter<'a> {
+    /// Unlogged batches carry no atomicity to lose, they are splitted freely.
+    pub fn new(scylla: &'a ScyllaWrapper, batch_type: BatchType) -> Self {
+        Self {
+            scylla,
+            split: !matches!(batch_type, BatchType::Logged),
+            batch_type,
+            max_statements: DEFAULT_MAX_STATEMENTS,
+            max_bytes: DEFAULT_MAX_BYTES,
+            idempotent: true,
+            statements: vec![],
+        }
+    }
+
+    /// Allow a batch over the limits to be sent as several ones.
+    pub fn split(mut self, split: bool) -> Self {
+        self.split = split;
+        self
+    }
+
+    pub fn idempotent(mut self, idempotent: bool) -> Self {
+        self.idempotent = idempotent;
+        self
+    }
+
+    pub fn max_statements(mut self, max_statements: usize) -> Self {
+        self.max_statements = max_statements.max(1);
+        self
+    }
+
+    pub fn max_bytes(mut self, max_bytes: usize) -> Self {
+        self.max_bytes = max_bytes;
+        self
+    }
+
+    pub fn add<S: Statement>(&mut self, statement: &S) -> Result<&mut Self, DatabaseError> {
+        let values = statement.values()?.into_owned();
+        self.statements
+            .push((Query::new(statement.query()), values));
+        Ok(self)
+    }
+
+    pub fn add_query(
+        &mut self,
+        query: &str,
+        values: impl ValueList,
+    ) -> Result<&mut Self, DatabaseError> {
+        let values = values.serialized()?.into_owned();
+        self.statements.push((Query::new(query), values));
+        Ok(self)
+    }
+
+    pub fn len(&self) -> usize {
+        self.statements.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.statements.is_empty()
+    }
+
+    /// Send every batches, one after another.
+    /// A failed batch doesn't stop the others, check the report for failures.
+    /// Nothing is sent if the batch is over the limits and can't be splitted.
+    pub async fn execute(self) -> Result<BatchReport, DatabaseError> {
+        let sizes: Vec<usize> = self
+            .statements
+            .iter()
+            .map(|(query, values)| query.contents.len() + values.size())
+            .collect();
+        let ranges = plan(&sizes, self.max_statements, self.max_bytes, self.split)?;
+        let mut report = BatchReport::default();
+        for range in ranges {
+            let mut batch = Batch::new(self.batch_type);
+            batch.set_is_idempotent(self.idempotent);
+            let mut values: Vec<&SerializedValues> = vec![];
+            for (query, value) in &self.statements[range.clone()] {
+                batch.append_statement(query.clone());
+                values.push(value);
+            }
+            let result = match self.idempotent {
+                true => {
+                    self.scylla
+                        .retry
+                        .run(|| self.scylla.session.batch(&batch, values.clone()))
+                        .await
+                }
+                false => self.scylla.session.batch(&batch, values).await,
+            };
+            match result {
+                Ok(_) => report.applied += range.len(),
+                Err(error) => {
+                    log::error!(
+                        "[scylla] batch of statements {:?} failed: {:?}",
+                        range,
+                        error
+                    );
+                    report.failures.push(BatchFailure {
+                        statements: range,
+                        error,
+                    });
+                }
+            };
+        }
+        Ok(report)
+    }
+}
+
+/// Split the statements if allowed to, refuse them if they don't fit in one batch otherwise.
+fn plan(
+    sizes: &[usize],
+    max_statements: usize,
+    max_bytes: usize,
+    allow_split: bool,
+) -> Result<Vec<Range<usize>>, DatabaseError> {
+    let ranges = split(sizes, max_statements, max_bytes);
+    if !allow_split && ranges.len() > 1 {
+        return Err(DatabaseError::ScyllaBatchTooLarge(sizes.len()));
+    }
+    Ok(ranges)
+}
+
+/// Split statements (given by their sizes) into consecutive groups,
+///     a statement bigger than `max_bytes` still get a group of its own.
+fn split(sizes: &[usize], max_statements: usize, max_bytes: usize) -> Vec<Range<usize>> {
+    let mut ranges = vec![];
+    let mut start = 0;
+    let mut bytes = 0;
+    for (i, size) in sizes.iter().enumerate() {
+        let full = i - start >= max_statements || bytes + size > max_bytes;
+        if i > start && full {
+            ranges.push(start..i);
+            start = i;
+            bytes = 0;
+        }
+        bytes += size;
+    }
+    if start < sizes.len() {
+        ranges.push(start..sizes.len());
+    }
+    ranges
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn split_nothing() {
+        assert_eq!(split(&[], 10, 100), vec![]);
+    }
+
+    #[test]
+    fn split_by_statement_count() {
+        assert_eq!(split(&[1; 5], 2, 100), vec![0..2, 2..4, 4..5]);
+    }
+
+    #[test]
+    fn split_by_size() {
+        assert_eq!(split(&[40, 40, 40, 10], 10, 100), vec![0..2, 2..4]);
+    }
+
+    #[test]
+    fn split_oversized_statement() {
+        assert_eq!(split(&[10, 500, 10], 10, 100), vec![0..1, 1..2, 2..3]);
+    }
+
+    #[test]
+    fn refuse_to_split_unless_allowed() {
+        assert_eq!(plan(&[40, 40], 10, 100, false).unwrap(), vec![0..2]);
+        assert_eq!(plan(&[500], 10, 100, false).unwrap(), vec![0..1]);
+        assert!(matches!(
+            plan(&[40, 40, 40], 10, 100, false),
+            Err(DatabaseError::ScyllaBatchTooLarge(3))
+        ));
+        assert_eq!(
+            plan(&[40, 40, 40], 10, 100, true).unwrap(),
+            vec![0..2, 2..3]
+        );
+    }
+}
@@ -0,0 +1,176 @@
+use super::ScyllaWrapper;
+use crate::database::error::DatabaseError;
+use scylla::IntoTypedRows;
+use std::collections::BTreeMap;
+
+/// A table as described by `system_schema`,
+///     column types use the cql notation (`text`, `bigint`, `list<text>`, ...).
+#[derive(Debug, Clone, PartialEq)]
+pub struct TableSchema {
+    pub name: String,
+    pub columns: BTreeMap<String, String>,
+}
+
+/// A difference between the schema we expect and the live one.
+#[derive(Debug, Clone, PartialEq)]
+pub enum SchemaDiff {
+    MissingTable(String),
+    ExtraTable(String),
+    MissingColumn {
+        table: String,
+        column: String,
+    },
+    ExtraColumn {
+        table: String,
+        column: String,
+    },
+    TypeMismatch {
+        table: String,
+        column: String,
+        expected: String,
+        actual: String,
+    },
+}
+
+impl TableSchema {
+    pub fn new(name: &str, columns: &[(&str, &str)]) -> Self {
+        Self {
+            name: name.to_owned(),
+            columns: columns
+                .iter()
+                .map(|(column, kind)| (column.to_string(), kind.to_string()))
+                .collect(),
+        }
+    }
+}
+
+impl ScyllaWrapper {
+    /// Read the schema of every table in the current keyspace.
+    pub async fn live_schema(&self) -> Result<Vec<TableSchema>, DatabaseError> {
+        const COLUMNS_QUERY: &str = r#"
+            SELECT table_name, column_name, type
+            FROM system_schema.columns
+            WHERE keyspace_name = ?
+        "#;
+
+        let query = Self::idempotent(COLUMNS_QUERY);
+        let result = self
+            .retry
+            .run(|| self.session.query(query.clone(), (self.keyspace.as_str(),)))
+            .await?;
+        let mut tables: BTreeMap<String, BTreeMap<String, String>> = BTreeMap::new();
+        for row in result
+            .rows
+            .unwrap_or_default()
+            .into_typed::<(String, String, String)>()
+        {
+            let (table, column, kind) = row?;
+            tables.entry(table).or_default().insert(column, kind);
+        }
+        Ok(tables
+            .into_iter()
+            .map(|(name, columns)| TableSchema { name, columns })
+            .collect())
+    }
+
+    /// Compare the live keyspace against the schema this code expects.
+    pub async fn schema_diff(&self) -> Result<Vec<SchemaDiff>, DatabaseError> {
+        Ok(diff(&super::schema::expected(), &self.live_schema().await?))
+    }
+}
+
+pub fn diff(expected: &[TableSchema], live: &[TableSchema]) -> Vec<SchemaDiff> {
+    let mut diffs = vec![];
+    for table in expected.iter() {
+        let live_table = match live.iter().find(|live| live.name == table.name) {
+            None => {
+                diffs.push(SchemaDiff::MissingTable(table.name.clone()));
+                continue;
+            }
+            Some(live_table) => live_table,
+        };
+        for (column, kind) in table.columns.iter() {
+            match live_table.columns.get(column) {
+                None => diffs.push(SchemaDiff::MissingColumn {
+                    table: table.name.clone(),
+                    column: column.clone(),
+                }),
+                Some(actual) if actual != kind => diffs.push(SchemaDiff::TypeMismatch {
+                    table: table.name.clone(),
+                    column: column.clone(),
+                    expected: kind.clone(),
+                    actual: actual.clone(),
+                }),
+                Some(_) => {}
+            };
+        }
+        for column in live_table.columns.keys() {
+            if !table.columns.contains_key(column) {
+                diffs.push(SchemaDiff::ExtraColumn {
+                    table: table.name.clone(),
+                    column: column.clone(),
+                });
+            }
+        }
+    }
+    for table in live.iter() {
+        if !expected.iter().any(|expected| expected.name == table.name) {
+            diffs.push(SchemaDiff::ExtraTable(table.name.clone()));
+        }
+    }
+    diffs
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn same_schema() {
+        let schema = vec![TableSchema::new(
+            "sync_data",
+            &[("field", "text"), ("value", "text")],
+        )];
+        assert_eq!(diff(&schema, &schema), vec![]);
+    }
+
+    #[test]
+    fn missing_and_extra_tables() {
+        let expected = vec![TableSchema::new("sync_data", &[("field", "text")])];
+        let live = vec![TableSchema::new("manual_backup", &[("field", "text")])];
+        assert_eq!(
+            diff(&expected, &live),
+            vec![
+                SchemaDiff::MissingTable("sync_data".to_owned()),
+                SchemaDiff::ExtraTable("manual_backup".to_owned()),
+            ]
+        );
+    }
+
+    #[test]
+    fn column_changes() {
+        let expected = vec![TableSchema::new(
+            "sync_data",
+            &[("field", "text"), ("value", "text")],
+        )];
+        let live = vec![TableSchema::new(
+            "sync_data",
+            &[("field", "text"), ("value", "bigint"), ("note", "text")],
+        )];
+        assert_eq!(
+            diff(&expected, &live),
+            vec![
+                SchemaDiff::TypeMismatch {
+                    table: "sync_data".to_owned(),
+                    column: "value".to_owned(),
+                    expected: "text".to_owned(),
+                    actual: "bigint".to_owned(),
+                },
+                SchemaDiff::ExtraColumn {
+                    table: "sync_data".to_owned(),
+                    column: "note".to_owned(),
+                },
+            ]
+        );
+    }
+}
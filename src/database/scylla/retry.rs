@@ -0,0 +1,111 @@
+use crate::server_config::scylla::ScyllaConfig;
+use scylla::transport::errors::{DbError, QueryError};
+use std::{future::Future, time::Duration};
+
+/// Upper bound of the delay between two retries.
+const MAX_BACKOFF: Duration = Duration::from_secs(5);
+
+/// Client side retry with exponential backoff.
+/// The driver's own retry policy retries immediately on the next node,
+///     this one is for riding out a whole cluster hiccup (e.g. during startup).
+/// Only use it for idempotent statements.
+#[derive(Clone, Debug)]
+pub struct RetryPolicy {
+    pub max_retries: u32,
+    pub backoff: Duration,
+}
+
+impl RetryPolicy {
+    pub fn new(config: &ScyllaConfig) -> Self {
+        Self {
+            max_retries: config.retry_max,
+            backoff: Duration::from_millis(config.retry_backoff),
+        }
+    }
+
+    /// Delay before the `attempt`-th retry (starting from 0).
+    pub fn delay(&self, attempt: u32) -> Duration {
+        self.backoff
+            .checked_mul(1 << attempt.min(16))
+            .unwrap_or(MAX_BACKOFF)
+            .min(MAX_BACKOFF)
+    }
+
+    pub async fn run<T, F, Fut>(&self, operation: F) -> Result<T, QueryError>
+    where
+        F: Fn() -> Fut,
+        Fut: Future<Output = Result<T, QueryError>>,
+    {
+        let mut attempt: u32 = 0;
+        loop {
+            match operation().await {
+                Err(err) if attempt < self.max_retries && is_transient(&err) => {
+                    let delay = self.delay(attempt);
+                    log::warn!(
+                        "[scylla] transient error, retrying in {:?} ({}/{}): {:?}",
+                        delay,
+                        attempt + 1,
+                        self.max_retries,
+                        err
+                    );
+                    tokio::time::sleep(delay).await;
+                    attempt += 1;
+                }
+                result => return result,
+            }
+        }
+    }
+}
+
+/// Whether an error might go away by simply trying again later.
+pub fn is_transient(err: &QueryError) -> bool {
+    match err {
+        QueryError::DbError(err, _) => matches!(
+            err,
+            DbError::Unavailable { .. }
+                | DbError::Overloaded
+                | DbError::IsBootstrapping
+                | DbError::ReadTimeout { .. }
+                | DbError::WriteTimeout { .. }
+        ),
+        QueryError::IoError(_)
+        | QueryError::TimeoutError
+        | QueryError::RequestTimeout(_)
+        | QueryError::TooManyOrphanedStreamIds(_)
+        | QueryError::UnableToAllocStreamId => true,
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{io, sync::Arc};
+
+    #[test]
+    fn delay_doubles_up_to_the_bound() {
+        let policy = RetryPolicy {
+            max_retries: 3,
+            backoff: Duration::from_millis(100),
+        };
+        assert_eq!(policy.delay(0), Duration::from_millis(100));
+        assert_eq!(policy.delay(1), Duration::from_millis(200));
+        assert_eq!(policy.delay(3), Duration::from_millis(800));
+        assert_eq!(policy.delay(10), MAX_BACKOFF);
+        assert_eq!(policy.delay(u32::MAX), MAX_BACKOFF);
+    }
+
+    #[test]
+    fn only_transient_errors_are_retried() {
+        let overloaded = QueryError::DbError(DbError::Overloaded, "".to_owned());
+        let reset = QueryError::IoError(Arc::new(io::Error::from(io::ErrorKind::ConnectionReset)));
+        assert!(is_transient(&overloaded));
+        assert!(is_transient(&reset));
+        assert!(is_transient(&QueryError::TimeoutError));
+
+        let syntax = QueryError::DbError(DbError::SyntaxError, "".to_owned());
+        let invalid = QueryError::InvalidMessage("".to_owned());
+        assert!(!is_transient(&syntax));
+        assert!(!is_transient(&invalid));
+    }
+}
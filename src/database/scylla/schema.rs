@@ -1,13 +1,15 @@
+use super::introspect::TableSchema;
 use crate::database::sync::Synchronizer;
 use std::sync::Arc;
 
 pub fn synchronizers() -> Arc<Vec<Synchronizer>> {
-    Arc::new(vec![master(), v_1()])
+    Arc::new(vec![master(), v_1(), v_2(), v_3()])
 }
 
 fn master() -> Synchronizer {
     Synchronizer::Simple(vec![
         "CREATE TABLE sync_data (field TEXT PRIMARY KEY, value TEXT);".to_owned(),
+        titles(),
     ])
 }
 
@@ -16,3 +18,64 @@ fn v_1() -> Synchronizer {
         "CREATE TABLE sync_data (field TEXT PRIMARY KEY, value TEXT);".to_owned(),
     ])
 }
+
+fn v_2() -> Synchronizer {
+    Synchronizer::Simple(vec![r#"CREATE TABLE titles (
+        id BIGINT PRIMARY KEY,
+        name TEXT,
+        alt_names LIST<TEXT>,
+        authors LIST<TEXT>,
+        tags SET<TEXT>,
+        publisher TEXT,
+        format TEXT,
+        status TEXT,
+        updated_at BIGINT
+    );"#
+    .to_owned()])
+}
+
+fn v_3() -> Synchronizer {
+    Synchronizer::Simple(vec!["ALTER TABLE titles ADD cover TEXT;".to_owned()])
+}
+
+/// `updated_at` is the last mutation time (in miliseconds),
+///     it is copied into the search index to detect stale documents.
+/// `cover` is the url of the cover thumbnail.
+fn titles() -> String {
+    r#"CREATE TABLE titles (
+        id BIGINT PRIMARY KEY,
+        name TEXT,
+        alt_names LIST<TEXT>,
+        authors LIST<TEXT>,
+        tags SET<TEXT>,
+        publisher TEXT,
+        format TEXT,
+        status TEXT,
+        cover TEXT,
+        updated_at BIGINT
+    );"#
+    .to_owned()
+}
+
+/// The schema that the latest synchronizer should leave the keyspace in,
+///     keep it in sync with `master()`.
+pub fn expected() -> Vec<TableSchema> {
+    vec![
+        TableSchema::new("sync_data", &[("field", "text"), ("value", "text")]),
+        TableSchema::new(
+            "titles",
+            &[
+                ("id", "bigint"),
+                ("name", "text"),
+                ("alt_names", "list<text>"),
+                ("authors", "list<text>"),
+                ("tags", "set<text>"),
+                ("publisher", "text"),
+                ("format", "text"),
+                ("status", "text"),
+                ("cover", "text"),
+                ("updated_at", "bigint"),
+            ],
+        ),
+    ]
+}
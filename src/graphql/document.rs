@@ -0,0 +1,285 @@
+//! Just enough of a GraphQL parser to tell what a request is about,
+//!     before handing it to juniper (which rejects invalid documents anyway).
+
+/// What an operation does.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Operation {
+    Query,
+    Mutation,
+}
+
+impl Operation {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Query => "query",
+            Self::Mutation => "mutation",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Token<'a> {
+    Name(&'a str),
+    Punctuator(&'a str),
+    /// Strings and numbers, as written.
+    Value(&'a str),
+}
+
+/// An operation of a document, as far as we can tell.
+#[derive(Debug, Clone, PartialEq)]
+pub struct OperationSummary {
+    pub kind: Operation,
+    pub name: Option<String>,
+    /// Fields selected at the top level (not aliases),
+    ///     `None` if some come from fragments.
+    pub fields: Option<Vec<String>>,
+}
+
+/// Split `document` into tokens, dropping whitespace, commas and comments.
+pub fn tokenize(document: &str) -> Vec<Token<'_>> {
+    let mut tokens = vec![];
+    let mut chars = document.char_indices().peekable();
+    while let Some((start, c)) = chars.next() {
+        match c {
+            '#' => {
+                for (_, c) in chars.by_ref() {
+                    if c == '\n' {
+                        break;
+                    }
+                }
+            }
+            '"' => {
+                let block = document[start..].starts_with("\"\"\"");
+                if block {
+                    chars.next();
+                    chars.next();
+                }
+                let mut end = document.len();
+                while let Some((index, c)) = chars.next() {
+                    match c {
+                        '\\' => {
+                            chars.next();
+                        }
+                        '"' if !block => {
+                            end = index + 1;
+                            break;
+                        }
+                        '"' if document[index..].starts_with("\"\"\"") => {
+                            chars.next();
+                            chars.next();
+                            end = index + 3;
+                            break;
+                        }
+                        _ => {}
+                    }
+                }
+                tokens.push(Token::Value(&document[start..end]));
+            }
+            '.' if document[start..].starts_with("...") => {
+                chars.next();
+                chars.next();
+                tokens.push(Token::Punctuator("..."));
+            }
+            c if c.is_ascii_alphabetic() || c == '_' || c == '-' || c.is_ascii_digit() => {
+                let name = c.is_ascii_alphabetic() || c == '_';
+                let mut end = start + 1;
+                while let Some((index, c)) = chars.peek() {
+                    let number = !name && matches!(*c, '.' | '+' | '-');
+                    if !c.is_ascii_alphanumeric() && *c != '_' && !number {
+                        break;
+                    }
+                    end = index + 1;
+                    chars.next();
+                }
+                let word = &document[start..end];
+                if name {
+                    tokens.push(Token::Name(word));
+                } else {
+                    tokens.push(Token::Value(word));
+                }
+            }
+            c if c.is_whitespace() || c == ',' || c == '\u{feff}' => {}
+            _ => tokens.push(Token::Punctuator(&document[start..start + c.len_utf8()])),
+        };
+    }
+    tokens
+}
+
+/// The same document whatever its comments and formatting.
+pub fn normalize(tokens: &[Token]) -> String {
+    let words: Vec<&str> = tokens
+        .iter()
+        .map(|token| match token {
+            Token::Name(word) | Token::Punctuator(word) | Token::Value(word) => *word,
+        })
+        .collect();
+    words.join(" ")
+}
+
+/// Every operation of the document, in order (fragments are left out).
+pub fn operations(tokens: &[Token]) -> Vec<OperationSummary> {
+    let mut operations = vec![];
+    // set once a definition starts: `None` kind for fragments.
+    let mut pending: Option<(Option<Operation>, Option<String>)> = None;
+    let mut current: Option<OperationSummary> = None;
+    let mut braces = 0;
+    let mut parens = 0;
+    let mut previous = None;
+    for (index, token) in tokens.iter().enumerate() {
+        let sigil = matches!(previous, Some(Token::Punctuator("@" | "$")));
+        match token {
+            Token::Punctuator("{") => {
+                if braces == 0 && parens == 0 {
+                    current = match pending.take() {
+                        None => Some(OperationSummary {
+                            kind: Operation::Query,
+                            name: None,
+                            fields: Some(vec![]),
+                        }),
+                        Some((None, _)) => None,
+                        Some((Some(kind), name)) => Some(OperationSummary {
+                            kind,
+                            name,
+                            fields: Some(vec![]),
+                        }),
+                    };
+                }
+                braces += 1;
+            }
+            Token::Punctuator("}") => {
+                braces -= 1;
+                if braces == 0 {
+                    operations.extend(current.take());
+                }
+            }
+            Token::Punctuator("(") => parens += 1,
+            Token::Punctuator(")") => parens -= 1,
+            Token::Punctuator("...") if braces == 1 => {
+                if let Some(operation) = &mut current {
+                    operation.fields = None;
+                }
+            }
+            Token::Name(word) if braces == 0 && parens == 0 && !sigil => {
+                match (*word, pending.is_none()) {
+                    ("query" | "subscription", true) => {
+                        pending = Some((Some(Operation::Query), None))
+                    }
+                    ("mutation", true) => pending = Some((Some(Operation::Mutation), None)),
+                    ("fragment", true) => pending = Some((None, None)),
+                    _ => {
+                        if let Some((_, name @ None)) = &mut pending {
+                            *name = Some(word.to_string());
+                        }
+                    }
+                };
+            }
+            Token::Name(word) if braces == 1 && parens == 0 && !sigil => {
+                let alias = tokens.get(index + 1) == Some(&Token::Punctuator(":"));
+                if let Some(OperationSummary {
+                    fields: Some(fields),
+                    ..
+                }) = &mut current
+                {
+                    if !alias {
+                        fields.push(word.to_string());
+                    }
+                }
+            }
+            _ => {}
+        };
+        previous = Some(*token);
+    }
+    operations
+}
+
+/// The operation that runs: the one named `name`, or the first one.
+pub fn select(tokens: &[Token], name: Option<&str>) -> Option<OperationSummary> {
+    let mut operations = operations(tokens).into_iter();
+    match name {
+        None => operations.next(),
+        Some(wanted) => operations.find(|operation| operation.name.as_deref() == Some(wanted)),
+    }
+}
+
+/// Find which kind of operation `document` runs,
+///     anything unclear counts as a query.
+pub fn operation(document: &str, name: Option<&str>) -> Operation {
+    select(&tokenize(document), name)
+        .map(|operation| operation.kind)
+        .unwrap_or(Operation::Query)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn anonymous_and_named_operations() {
+        assert_eq!(operation("{ titles { id } }", None), Operation::Query);
+        assert_eq!(operation("query { a }", None), Operation::Query);
+        assert_eq!(
+            operation("mutation Add($name: String) { add(name: $name) }", None),
+            Operation::Mutation
+        );
+        assert_eq!(operation("", None), Operation::Query);
+    }
+
+    #[test]
+    fn select_by_operation_name() {
+        let document = r#"
+            query List { titles { id } }
+            mutation Add { add(name: "query") { id } }
+        "#;
+        assert_eq!(operation(document, None), Operation::Query);
+        assert_eq!(operation(document, Some("List")), Operation::Query);
+        assert_eq!(operation(document, Some("Add")), Operation::Mutation);
+    }
+
+    #[test]
+    fn ignore_fragments_comments_strings_and_directives() {
+        let document = r#"
+            # mutation Fake { a }
+            fragment Fields on Title { id name }
+            mutation @mutation(reason: """query { a } \""" mutation""") { add { ...Fields } }
+        "#;
+        assert_eq!(operation(document, None), Operation::Mutation);
+        assert_eq!(
+            operation("query Q($mutation: Int = 1) { a }", Some("Q")),
+            Operation::Query
+        );
+    }
+
+    #[test]
+    fn top_level_fields() {
+        let tokens = tokenize(
+            r#"query Home($q: String!) {
+                results: search(query: $q, page: -1) { items { id } }
+                suggest(prefix: "one") @include(if: true) { id }
+                __typename
+            }"#,
+        );
+        let operation = select(&tokens, None).unwrap();
+        assert_eq!(
+            operation.fields,
+            Some(vec![
+                "search".to_string(),
+                "suggest".to_string(),
+                "__typename".to_string()
+            ])
+        );
+
+        let tokens = tokenize("fragment F on Query { search { total } } { ...F }");
+        assert_eq!(select(&tokens, None).unwrap().fields, None);
+    }
+
+    #[test]
+    fn normalize_formatting_and_comments() {
+        let a = tokenize("{ search(query: \"a  b\", page: 1.5e3) { total } }");
+        let b = tokenize("# home\n{\n  search(query:\"a  b\" page:1.5e3){total}\n}\n");
+        assert_eq!(normalize(&a), normalize(&b));
+        assert_eq!(
+            normalize(&a),
+            "{ search ( query : \"a  b\" page : 1.5e3 ) { total } }"
+        );
+    }
+}
@@ -1,9 +1,13 @@
+use crate::database::error::DatabaseError;
 use juniper::{graphql_value, FieldError, IntoFieldError, ScalarValue};
 
 #[derive(Debug, Clone)]
 pub enum GraphQLError {
     /// When the error is made by the user (invalid input, unauthorized, etc...)
     ClientFault(ClientFault),
+
+    /// When the error is on our side, the client can only retry later.
+    ServerFault(ServerFault),
 }
 
 #[derive(Debug, Clone)]
@@ -13,12 +17,28 @@ pub enum ClientFault {
 
     /// The input is not a valid integer, return back what causes.
     InvalidInteger(String),
+
+    /// The cursor is not one that we handed out, return back what causes.
+    InvalidCursor(String),
+
+    /// The cursor was handed out by another query, return back what causes.
+    MismatchedCursor(String),
+
+    /// The requested page size is out of the allowed range.
+    InvalidPageSize(i32),
+}
+
+#[derive(Debug, Clone)]
+pub enum ServerFault {
+    /// One of the databases failed, the details are only logged.
+    DatabaseError(),
 }
 
 impl GraphQLError {
     fn name(&self) -> String {
         match self {
             Self::ClientFault(err) => format!("client fault - {}", err.name()),
+            Self::ServerFault(err) => format!("server fault - {}", err.name()),
         }
     }
 }
@@ -34,11 +54,29 @@ impl<S: ScalarValue> IntoFieldError<S> for GraphQLError {
     }
 }
 
+impl From<DatabaseError> for GraphQLError {
+    fn from(err: DatabaseError) -> Self {
+        log::error!("database error: {:?}", err);
+        GraphQLError::ServerFault(ServerFault::DatabaseError())
+    }
+}
+
 impl ClientFault {
     fn name(&self) -> String {
         match self {
             Self::MustNotEmpty() => "must not empty".to_owned(),
             Self::InvalidInteger(_) => "invalid integer".to_owned(),
+            Self::InvalidCursor(_) => "invalid cursor".to_owned(),
+            Self::MismatchedCursor(_) => "mismatched cursor".to_owned(),
+            Self::InvalidPageSize(_) => "invalid page size".to_owned(),
+        }
+    }
+}
+
+impl ServerFault {
+    fn name(&self) -> String {
+        match self {
+            Self::DatabaseError() => "database error".to_owned(),
         }
     }
 }
@@ -1,17 +1,58 @@
 use crate::database::bundle::Database;
-use crate::graphql::{context::Context, schema::Schema};
-use actix_web::{route, web, Error, HttpResponse};
+use crate::graphql::{
+    context::Context, document, rate_limit, response_cache::Policy, schema::Schema,
+};
+use crate::server_config::rate_limit::RateLimitConfig;
+use actix_web::{
+    http::header::{self, ContentType},
+    route, web, Error, HttpRequest, HttpResponse,
+};
 use juniper::http::GraphQLRequest;
 use std::sync::Arc;
 
 #[route("/graphql", method = "POST")]
 pub async fn graphql(
+    req: HttpRequest,
     schema: web::Data<Arc<Schema>>,
     database: web::Data<Arc<Database>>,
+    limits: web::Data<RateLimitConfig>,
     data: web::Json<GraphQLRequest>,
 ) -> Result<HttpResponse, Error> {
+    if let Some(client) = rate_limit::client(&req, &limits) {
+        let operation = document::operation(&data.query, data.operation_name.as_deref());
+        if let Some(retry_after) =
+            rate_limit::check(&database.redis, &limits, &client, operation).await
+        {
+            return Ok(HttpResponse::TooManyRequests()
+                .insert_header((header::RETRY_AFTER, retry_after.to_string()))
+                .json(serde_json::json!({
+                    "errors": [{ "message": "too many requests, retry later" }]
+                })));
+        }
+    }
+
+    let policy = Policy::of(&data);
+    if let Some(policy) = &policy {
+        if let Some(body) = policy.lookup(&database.cache).await {
+            return Ok(HttpResponse::Ok()
+                .content_type(ContentType::json())
+                .body(body));
+        }
+    }
+
+    let cache = database.cache.clone();
     let ctx = Context::new(database);
 
     let res = data.execute(&schema, &ctx).await;
-    Ok(HttpResponse::Ok().json(res))
+    let policy = match policy {
+        None => return Ok(HttpResponse::Ok().json(res)),
+        Some(policy) => policy,
+    };
+    // field errors (e.g. manticore failing) are still an `Ok` response, only
+    //     `errors` tells them apart, and those may be transient: never cached.
+    let body = serde_json::to_value(&res)?;
+    if res.is_ok() && body.get("errors").is_none() {
+        policy.store(&cache, &body.to_string()).await;
+    }
+    Ok(HttpResponse::Ok().json(body))
 }
@@ -1,11 +1,17 @@
 use actix_web::web;
 
 pub mod context;
+pub mod document;
 pub mod error;
 mod handler;
 pub mod mutation;
+pub mod pagination;
 pub mod query;
+pub mod rate_limit;
+pub mod response_cache;
 pub mod schema;
+pub mod search;
+pub mod title;
 
 pub fn route(cfg: &mut web::ServiceConfig) {
     cfg.service(handler::graphql);
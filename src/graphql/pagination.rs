@@ -0,0 +1,162 @@
+use super::error::{ClientFault, GraphQLError};
+use crate::database::error::DatabaseError;
+use crate::database::scylla::ScyllaPage;
+use crate::model::{Cursor, CursorPosition, Snowflake};
+use juniper::GraphQLObject;
+use scylla::frame::value::ValueList;
+
+/// Page size used when the client don't ask for any.
+pub const DEFAULT_PAGE_SIZE: i32 = 20;
+
+/// Upper bound of a page, so one request can't scan a whole partition.
+pub const MAX_PAGE_SIZE: i32 = 100;
+
+/// Relay's `PageInfo`.
+/// Pagination is forward only, so `has_previous_page` is simply
+///     whether the client already moved past the first page.
+#[derive(GraphQLObject, Debug, Clone)]
+pub struct PageInfo {
+    pub has_next_page: bool,
+    pub has_previous_page: bool,
+    pub start_cursor: Option<Cursor>,
+    pub end_cursor: Option<Cursor>,
+}
+
+/// Validated `first` / `after` arguments of a connection field.
+/// `shape` is the fingerprint of the query (and bound values) the page comes from,
+///     every cursor handed out carry it.
+#[derive(Debug, Clone)]
+pub struct PageRequest {
+    pub first: i32,
+    pub after: Option<Cursor>,
+    pub shape: u32,
+}
+
+/// Anything that can be put in a connection's edge.
+pub trait Node {
+    /// Position right after this node.
+    fn position(&self) -> CursorPosition;
+}
+
+impl Node for Snowflake {
+    fn position(&self) -> CursorPosition {
+        CursorPosition::from(self)
+    }
+}
+
+impl PageRequest {
+    /// `query` and `values` describe what the page is read with (see `Cursor::shape_of`),
+    ///     a cursor made by another query or for another partition
+    ///     is rejected rather than resumed from.
+    pub fn new(
+        query: &str,
+        values: &impl ValueList,
+        first: Option<i32>,
+        after: Option<Cursor>,
+    ) -> Result<Self, GraphQLError> {
+        let values = values.serialized().map_err(DatabaseError::from)?;
+        let first = first.unwrap_or(DEFAULT_PAGE_SIZE);
+        if first < 1 || first > MAX_PAGE_SIZE {
+            return Err(GraphQLError::ClientFault(ClientFault::InvalidPageSize(
+                first,
+            )));
+        }
+        if let Some(cursor) = &after {
+            if !cursor.is_from(query, &values) {
+                return Err(GraphQLError::ClientFault(ClientFault::MismatchedCursor(
+                    cursor.encode(),
+                )));
+            }
+        }
+        Ok(Self {
+            first,
+            after,
+            shape: Cursor::shape_of(query, &values),
+        })
+    }
+
+    /// Cursor at `position` of this request's query.
+    pub fn cursor(&self, position: CursorPosition) -> Cursor {
+        Cursor {
+            shape: self.shape,
+            position,
+        }
+    }
+
+    /// Scylla paging state to resume from, if the cursor carry one.
+    pub fn paging_state(&self) -> Option<Vec<u8>> {
+        self.after.as_ref().and_then(|cursor| cursor.paging_state())
+    }
+
+    /// Snowflake to resume after, for lists that are queried by id range
+    ///     (`WHERE ... AND id < ?`) instead of paging state.
+    pub fn after_snowflake(&self) -> Option<Snowflake> {
+        self.after.as_ref().and_then(|cursor| cursor.snowflake())
+    }
+
+    /// Build the page info of a page fetched with this request.
+    /// The end cursor prefer scylla's paging state, since it let the next query
+    ///     resume exactly where this one stopped. When there is none,
+    ///     fallback to the last node's own cursor.
+    pub fn page_info<T: Node>(&self, page: &ScyllaPage<T>) -> PageInfo {
+        let end_cursor = match &page.paging_state {
+            Some(state) => Some(self.cursor(CursorPosition::PagingState(state.clone()))),
+            None => page.rows.last().map(|node| self.cursor(node.position())),
+        };
+        PageInfo {
+            has_next_page: page.paging_state.is_some(),
+            has_previous_page: self.after.is_some(),
+            start_cursor: page.rows.first().map(|node| self.cursor(node.position())),
+            end_cursor,
+        }
+    }
+}
+
+/// Declare a Relay connection (and its edge) for a node type.
+///
+/// ```ignore
+/// connection!(TitleConnection, TitleEdge, Title);
+///
+/// let request = PageRequest::new(QUERY, &values, first, after)?;
+/// let page = database.scylla.query_page(QUERY, values, request.first, request.paging_state()).await?;
+/// Ok(TitleConnection::new(page, &request))
+/// ```
+#[macro_export]
+macro_rules! connection {
+    ($connection:ident, $edge:ident, $node:ty) => {
+        #[derive(juniper::GraphQLObject)]
+        #[graphql(context = $crate::graphql::context::Context)]
+        pub struct $edge {
+            pub node: $node,
+            pub cursor: $crate::model::Cursor,
+        }
+
+        #[derive(juniper::GraphQLObject)]
+        #[graphql(context = $crate::graphql::context::Context)]
+        pub struct $connection {
+            pub edges: Vec<$edge>,
+            pub page_info: $crate::graphql::pagination::PageInfo,
+        }
+
+        impl $connection {
+            pub fn new(
+                page: $crate::database::scylla::ScyllaPage<$node>,
+                request: &$crate::graphql::pagination::PageRequest,
+            ) -> Self {
+                use $crate::graphql::pagination::Node;
+                let page_info = request.page_info(&page);
+                Self {
+                    edges: page
+                        .rows
+                        .into_iter()
+                        .map(|node| $edge {
+                            cursor: request.cursor(node.position()),
+                            node,
+                        })
+                        .collect(),
+                    page_info,
+                }
+            }
+        }
+    };
+}
@@ -1,4 +1,11 @@
-use super::context::Context;
+use super::{
+    context::Context,
+    error::GraphQLError,
+    search::{self, SearchFilters, SearchPage, SearchResult, Suggestion},
+    title::{self, TitleConnection},
+};
+use crate::database::manticore::search::TitleFilters;
+use crate::model::Cursor;
 use juniper::FieldResult;
 
 pub struct Query;
@@ -8,4 +15,48 @@ impl Query {
     async fn health_check(_ctx: &Context) -> FieldResult<bool> {
         Ok(true)
     }
+
+    /// Full-text search over titles (names, alternative names, authors, publisher, tags).
+    /// Diacritics are optional and kana can be typed in romaji.
+    async fn search(
+        ctx: &Context,
+        query: String,
+        filters: Option<SearchFilters>,
+        page: Option<i32>,
+        per_page: Option<i32>,
+    ) -> Result<SearchResult, GraphQLError> {
+        let page = SearchPage::new(page, per_page)?;
+        let filters: TitleFilters = filters.unwrap_or_default().into();
+        let manticore = ctx.database.manticore.clone();
+        let result = manticore
+            .search(query.clone(), filters, page.offset(), page.limit())
+            .await?;
+        let suggestion = match result.total {
+            0 if !query.trim().is_empty() => {
+                manticore
+                    .blocking(move |manticore| manticore.suggest_keywords(&query))
+                    .await?
+            }
+            _ => None,
+        };
+        Ok(SearchResult::new(result, &page, suggestion))
+    }
+
+    /// Every title, `after` is the cursor of an edge or the `endCursor` of a previous page.
+    async fn titles(
+        ctx: &Context,
+        first: Option<i32>,
+        after: Option<Cursor>,
+    ) -> Result<TitleConnection, GraphQLError> {
+        title::titles(&ctx.database, first, after).await
+    }
+
+    /// Search-as-you-type over title names.
+    async fn suggest(
+        ctx: &Context,
+        prefix: String,
+        limit: Option<i32>,
+    ) -> Result<Vec<Suggestion>, GraphQLError> {
+        search::suggest(&ctx.database, &prefix, limit).await
+    }
 }
@@ -0,0 +1,122 @@
+use super::document::Operation;
+use crate::database::redis::RedisWrapper;
+use crate::server_config::rate_limit::RateLimitConfig;
+use actix_web::HttpRequest;
+
+/// Set (or appended to) by each reverse proxy.
+const FORWARDED_FOR: &str = "x-forwarded-for";
+
+/// Prefix of the redis keys holding each client's budget.
+const RATE_LIMIT_PREFIX: &str = "ratelimit:";
+
+/// Who the budget is counted against.
+/// A request with fewer hops than there are trusted proxies didn't go through
+///     all of them, it's counted against the peer address instead.
+/// There's no authentication yet, once there is, authenticated users
+///     should be keyed by their id instead (and not share one with their network).
+pub fn client(req: &HttpRequest, config: &RateLimitConfig) -> Option<String> {
+    let peer = || req.peer_addr().map(|addr| addr.ip().to_string());
+    let ip = match config.trusted_proxies {
+        0 => peer(),
+        trusted => {
+            let hops: Vec<&str> = req
+                .headers()
+                .get_all(FORWARDED_FOR)
+                .filter_map(|value| value.to_str().ok())
+                .collect();
+            forwarded_client(&hops.join(","), trusted).or_else(peer)
+        }
+    };
+    ip.map(|ip| format!("ip:{}", ip))
+}
+
+/// The address the outermost of `trusted` proxies saw the request coming from.
+/// Each proxy appends to `X-Forwarded-For`, so only the last hops can be
+///     trusted, anything before them is whatever the client sent.
+fn forwarded_client(forwarded_for: &str, trusted: usize) -> Option<String> {
+    let hops: Vec<&str> = forwarded_for
+        .split(',')
+        .map(str::trim)
+        .filter(|hop| !hop.is_empty())
+        .collect();
+    hops.get(hops.len().checked_sub(trusted)?)
+        .map(|hop| hop.to_string())
+}
+
+/// Count the request against the client's budget, return `None` if it may go on,
+///     otherwise how many seconds to wait.
+/// Redis failing lets every request through, rather than taking the api down.
+pub async fn check(
+    redis: &RedisWrapper,
+    config: &RateLimitConfig,
+    client: &str,
+    operation: Operation,
+) -> Option<u64> {
+    let budget = match operation {
+        Operation::Query => config.queries,
+        Operation::Mutation => config.mutations,
+    };
+    if budget == 0 {
+        return None;
+    }
+    let key = format!("{}{}:{}", RATE_LIMIT_PREFIX, operation.name(), client);
+    let interval = (config.window / budget).max(1);
+    match redis.throttle(&key, interval, budget).await {
+        Err(err) => {
+            log::warn!("[rate limit] failed to check {:?}: {:?}", key, err);
+            None
+        }
+        Ok(0) => None,
+        Ok(wait) => Some((wait + 999) / 1000),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::test::TestRequest;
+
+    #[test]
+    fn client_is_the_hop_added_by_the_outermost_proxy() {
+        // sent by the client, then appended by two proxies.
+        let header = "6.6.6.6, 203.0.113.7, 10.0.0.2";
+        assert_eq!(forwarded_client(header, 1).as_deref(), Some("10.0.0.2"));
+        assert_eq!(forwarded_client(header, 2).as_deref(), Some("203.0.113.7"));
+        assert_eq!(
+            forwarded_client("203.0.113.7", 1).as_deref(),
+            Some("203.0.113.7")
+        );
+    }
+
+    #[test]
+    fn missing_hops() {
+        assert_eq!(forwarded_client("203.0.113.7", 2), None);
+        assert_eq!(forwarded_client("", 1), None);
+        assert_eq!(forwarded_client(" , ", 1), None);
+    }
+
+    #[test]
+    fn fallback_to_the_peer_address() {
+        let config = RateLimitConfig {
+            window: 60 * 1000,
+            queries: 300,
+            mutations: 30,
+            trusted_proxies: 2,
+        };
+        let peer = "198.51.100.4:4242".parse().unwrap();
+        let direct = TestRequest::default().peer_addr(peer).to_http_request();
+        assert_eq!(client(&direct, &config).as_deref(), Some("ip:198.51.100.4"));
+
+        let short = TestRequest::default()
+            .peer_addr(peer)
+            .insert_header((FORWARDED_FOR, "203.0.113.7"))
+            .to_http_request();
+        assert_eq!(client(&short, &config).as_deref(), Some("ip:198.51.100.4"));
+
+        let proxied = TestRequest::default()
+            .peer_addr(peer)
+            .insert_header((FORWARDED_FOR, "203.0.113.7, 10.0.0.2"))
+            .to_http_request();
+        assert_eq!(client(&proxied, &config).as_deref(), Some("ip:203.0.113.7"));
+    }
+}
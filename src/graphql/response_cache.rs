@@ -0,0 +1,118 @@
+use super::{
+    document::{self, Operation},
+    schema::CACHED_FIELDS,
+};
+use crate::database::cache::CacheWrapper;
+use juniper::http::GraphQLRequest;
+use sha2::{Digest, Sha256};
+
+/// Prefix of the redis keys holding whole responses.
+const RESPONSE_PREFIX: &str = "graphql:";
+
+/// Every client sees the same data, as there's no authentication yet.
+/// Once there is, responses that depend on the user must be keyed by their scope.
+const PUBLIC_SCOPE: &str = "public";
+
+/// A top-level field whose responses may be cached.
+pub struct CachedField {
+    pub name: &'static str,
+    /// How long the response is kept, in miliseconds.
+    pub ttl: usize,
+    /// Tags of the entities it depends on, see `CacheWrapper::invalidate_tag`.
+    pub tags: &'static [&'static str],
+}
+
+/// Where and how long a response is cached.
+pub struct Policy {
+    key: String,
+    ttl: usize,
+    tags: Vec<String>,
+}
+
+impl Policy {
+    /// `None` unless `request` is a query selecting only fields of `CACHED_FIELDS`,
+    ///     it's then kept as long as the shortest of their TTLs.
+    pub fn of(request: &GraphQLRequest) -> Option<Self> {
+        let tokens = document::tokenize(&request.query);
+        let operation = document::select(&tokens, request.operation_name.as_deref())?;
+        if operation.kind != Operation::Query {
+            return None;
+        }
+        let mut ttl: Option<usize> = None;
+        let mut tags: Vec<String> = vec![];
+        for field in operation.fields?.iter() {
+            if field == "__typename" {
+                continue;
+            }
+            let cached = CACHED_FIELDS.iter().find(|cached| cached.name == field)?;
+            ttl = Some(ttl.map_or(cached.ttl, |ttl| ttl.min(cached.ttl)));
+            for tag in cached.tags.iter() {
+                if !tags.iter().any(|known| known == tag) {
+                    tags.push(tag.to_string());
+                }
+            }
+        }
+        // objects are sorted by key once converted, so the order variables are sent in doesn't matter.
+        let variables = serde_json::to_value(&request.variables).ok()?.to_string();
+        Some(Self {
+            key: key(
+                PUBLIC_SCOPE,
+                &document::normalize(&tokens),
+                request.operation_name.as_deref(),
+                &variables,
+            ),
+            ttl: ttl?,
+            tags,
+        })
+    }
+
+    /// The cached response body, the cache failing counts as a miss.
+    pub async fn lookup(&self, cache: &CacheWrapper) -> Option<String> {
+        match cache.get::<_, String>(self.key.as_str()).await {
+            Err(err) => {
+                log::warn!("[graphql] failed to read a cached response: {:?}", err);
+                None
+            }
+            Ok(body) => body,
+        }
+    }
+
+    pub async fn store(&self, cache: &CacheWrapper, body: &str) {
+        // tagged first, so an invalidation can't miss a response that's been written.
+        let result = match cache.tag(&self.key, &self.tags).await {
+            Ok(_) => cache.set(self.key.as_str(), &body, self.ttl).await,
+            err => err,
+        };
+        if let Err(err) = result {
+            log::warn!("[graphql] failed to cache a response: {:?}", err);
+        }
+    }
+}
+
+fn key(scope: &str, document: &str, operation: Option<&str>, variables: &str) -> String {
+    let mut hasher = Sha256::new();
+    for part in [scope, document, operation.unwrap_or(""), variables].iter() {
+        // length-prefixed, so parts can't run into each other.
+        hasher.update((part.len() as u64).to_le_bytes());
+        hasher.update(part.as_bytes());
+    }
+    format!("{}{:x}", RESPONSE_PREFIX, hasher.finalize())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn key_covers_every_part() {
+        let base = key("public", "{ search }", None, "null");
+        assert!(base.starts_with(RESPONSE_PREFIX));
+        assert_eq!(base.len(), RESPONSE_PREFIX.len() + 64);
+        assert_eq!(base, key("public", "{ search }", None, "null"));
+        assert_ne!(base, key("user:1", "{ search }", None, "null"));
+        assert_ne!(base, key("public", "{ suggest }", None, "null"));
+        assert_ne!(base, key("public", "{ search }", Some("Home"), "null"));
+        assert_ne!(base, key("public", "{ search }", None, "{\"q\":1}"));
+        assert_ne!(key("ab", "c", None, ""), key("a", "bc", None, ""));
+    }
+}
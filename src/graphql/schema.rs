@@ -1,6 +1,22 @@
-use super::{context::Context, mutation::Mutation, query::Query};
+use super::{context::Context, mutation::Mutation, query::Query, response_cache::CachedField};
+use crate::database::cache::tag::TITLES_TAG;
 use juniper::{self, EmptySubscription};
 
+/// Top-level fields whose whole response may be cached (see `response_cache.rs`),
+///     a query is only cached if it selects nothing else.
+pub const CACHED_FIELDS: &[CachedField] = &[
+    CachedField {
+        name: "search",
+        ttl: 30 * 1000,
+        tags: &[TITLES_TAG],
+    },
+    CachedField {
+        name: "suggest",
+        ttl: 60 * 1000,
+        tags: &[TITLES_TAG],
+    },
+];
+
 pub type Schema = juniper::RootNode<'static, Query, Mutation, EmptySubscription<Context>>;
 
 pub fn create_schema() -> Schema {
@@ -0,0 +1,247 @@
+use super::{
+    error::{ClientFault, GraphQLError},
+    pagination::{DEFAULT_PAGE_SIZE, MAX_PAGE_SIZE},
+};
+use crate::database::bundle::Database;
+use crate::database::cache::{
+    load::LoadOptions,
+    tag::{title_tag, SUGGEST_NAMESPACE},
+};
+use crate::database::manticore::search::{
+    FacetCount, TitleFilters, TitleHit, TitleSearchResult, TitleSuggestion,
+};
+use crate::model::Snowflake;
+use juniper::{GraphQLInputObject, GraphQLObject};
+
+/// Autocompletion results are cached briefly (in miliseconds),
+///     the same prefixes are typed over and over.
+const SUGGEST_CACHE: LoadOptions = LoadOptions {
+    ttl: 60 * 1000,
+    stale: 5 * 60 * 1000,
+};
+
+const DEFAULT_SUGGEST_LIMIT: i32 = 10;
+const MAX_SUGGEST_LIMIT: i32 = 20;
+
+/// Manticore doesn't return matches past its `max_matches` (1000 by default),
+///     deeper pages are brought back to the last one it can serve.
+const MAX_SEARCH_RESULTS: i32 = 1000;
+
+/// Shorter prefixes match way too many titles to be useful.
+const MIN_PREFIX_LENGTH: usize = 2;
+
+/// Longer prefixes are cut, it's a title not an essay.
+const MAX_PREFIX_LENGTH: usize = 64;
+
+#[derive(GraphQLInputObject, Debug, Clone, Default)]
+pub struct SearchFilters {
+    pub publishers: Option<Vec<String>>,
+    pub formats: Option<Vec<String>>,
+    pub statuses: Option<Vec<String>>,
+}
+
+#[derive(GraphQLObject, Debug, Clone)]
+pub struct SearchHit {
+    pub id: Snowflake,
+    pub name: String,
+    /// The matched name, with keywords wrapped in `<b></b>`.
+    pub highlight: String,
+    pub weight: i32,
+}
+
+#[derive(GraphQLObject, Debug, Clone)]
+pub struct SearchFacet {
+    pub value: String,
+    pub count: i32,
+}
+
+#[derive(GraphQLObject, Debug, Clone)]
+pub struct SearchFacets {
+    pub publishers: Vec<SearchFacet>,
+    pub formats: Vec<SearchFacet>,
+    pub statuses: Vec<SearchFacet>,
+}
+
+#[derive(GraphQLObject, Debug, Clone)]
+pub struct SearchResult {
+    pub hits: Vec<SearchHit>,
+    pub total: i32,
+    pub page: i32,
+    pub facets: SearchFacets,
+    /// "Did you mean" keywords, only when nothing has been found.
+    pub suggestion: Option<String>,
+}
+
+#[derive(GraphQLObject, Debug, Clone)]
+pub struct Suggestion {
+    pub id: Snowflake,
+    pub name: String,
+    /// Url of the cover thumbnail.
+    pub cover: Option<String>,
+}
+
+/// Page number (starting from 1) and page size of a search.
+/// The page is capped so it ends within `MAX_SEARCH_RESULTS`.
+#[derive(Debug, Clone)]
+pub struct SearchPage {
+    pub page: i32,
+    pub per_page: i32,
+}
+
+impl SearchPage {
+    pub fn new(page: Option<i32>, per_page: Option<i32>) -> Result<Self, GraphQLError> {
+        let page = page.unwrap_or(1);
+        if page < 1 {
+            return Err(GraphQLError::ClientFault(ClientFault::InvalidInteger(
+                page.to_string(),
+            )));
+        }
+        let per_page = per_page.unwrap_or(DEFAULT_PAGE_SIZE);
+        if per_page < 1 || per_page > MAX_PAGE_SIZE {
+            return Err(GraphQLError::ClientFault(ClientFault::InvalidPageSize(
+                per_page,
+            )));
+        }
+        let page = page.min(MAX_SEARCH_RESULTS / per_page);
+        Ok(Self { page, per_page })
+    }
+
+    pub fn offset(&self) -> u32 {
+        ((self.page - 1) as u32).saturating_mul(self.per_page as u32)
+    }
+
+    pub fn limit(&self) -> u32 {
+        self.per_page as u32
+    }
+}
+
+impl From<SearchFilters> for TitleFilters {
+    fn from(filters: SearchFilters) -> Self {
+        Self {
+            publishers: filters.publishers.unwrap_or_default(),
+            formats: filters.formats.unwrap_or_default(),
+            statuses: filters.statuses.unwrap_or_default(),
+        }
+    }
+}
+
+impl From<TitleHit> for SearchHit {
+    fn from(hit: TitleHit) -> Self {
+        Self {
+            id: Snowflake(hit.id),
+            name: hit.name,
+            highlight: hit.highlight,
+            weight: hit.weight.clamp(0, i32::MAX as i64) as i32,
+        }
+    }
+}
+
+impl From<TitleSuggestion> for Suggestion {
+    fn from(suggestion: TitleSuggestion) -> Self {
+        Self {
+            id: Snowflake(suggestion.id),
+            name: suggestion.name,
+            cover: Some(suggestion.cover).filter(|cover| !cover.is_empty()),
+        }
+    }
+}
+
+impl From<FacetCount> for SearchFacet {
+    fn from(facet: FacetCount) -> Self {
+        Self {
+            value: facet.value,
+            count: facet.count.clamp(0, i32::MAX as i64) as i32,
+        }
+    }
+}
+
+impl SearchResult {
+    pub fn new(result: TitleSearchResult, page: &SearchPage, suggestion: Option<String>) -> Self {
+        Self {
+            hits: result.hits.into_iter().map(SearchHit::from).collect(),
+            total: result.total.min(i32::MAX as u64) as i32,
+            page: page.page,
+            facets: SearchFacets {
+                publishers: result
+                    .publishers
+                    .into_iter()
+                    .map(SearchFacet::from)
+                    .collect(),
+                formats: result.formats.into_iter().map(SearchFacet::from).collect(),
+                statuses: result.statuses.into_iter().map(SearchFacet::from).collect(),
+            },
+            suggestion,
+        }
+    }
+}
+
+/// Autocomplete title names, served from the cache when possible.
+pub async fn suggest(
+    database: &Database,
+    prefix: &str,
+    limit: Option<i32>,
+) -> Result<Vec<Suggestion>, GraphQLError> {
+    let limit = limit.unwrap_or(DEFAULT_SUGGEST_LIMIT);
+    if limit < 1 || limit > MAX_SUGGEST_LIMIT {
+        return Err(GraphQLError::ClientFault(ClientFault::InvalidPageSize(
+            limit,
+        )));
+    }
+    let prefix: String = prefix
+        .trim()
+        .to_lowercase()
+        .chars()
+        .take(MAX_PREFIX_LENGTH)
+        .collect();
+    if prefix.chars().count() < MIN_PREFIX_LENGTH {
+        return Ok(vec![]);
+    }
+
+    let key = format!("{}:{}", limit, prefix);
+    let key = database
+        .cache
+        .namespaced(SUGGEST_NAMESPACE, &key)
+        .await
+        .unwrap_or_else(|err| {
+            log::warn!("failed to read the suggestions namespace: {:?}", err);
+            format!("{}:{}", SUGGEST_NAMESPACE, key)
+        });
+    let manticore = database.manticore.clone();
+    let suggestions: Vec<TitleSuggestion> = database
+        .cache
+        .get_or_load_tagged(
+            &key,
+            SUGGEST_CACHE,
+            move || async move {
+                manticore
+                    .blocking(move |manticore| manticore.suggest_titles(&prefix, limit as u32))
+                    .await
+            },
+            |suggestions: &Vec<TitleSuggestion>| {
+                suggestions
+                    .iter()
+                    .map(|suggestion| title_tag(suggestion.id))
+                    .collect()
+            },
+        )
+        .await?;
+    Ok(suggestions.into_iter().map(Suggestion::from).collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn page_ends_within_max_matches() {
+        let page = SearchPage::new(Some(3), Some(20)).unwrap();
+        assert_eq!((page.offset(), page.limit()), (40, 20));
+
+        let page = SearchPage::new(Some(i32::MAX), Some(30)).unwrap();
+        assert_eq!(page.page, 33);
+        assert!(page.offset() + page.limit() <= MAX_SEARCH_RESULTS as u32);
+
+        assert!(SearchPage::new(Some(0), None).is_err());
+        assert!(SearchPage::new(None, Some(MAX_PAGE_SIZE + 1)).is_err());
+    }
+}
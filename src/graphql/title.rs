@@ -0,0 +1,157 @@
+use super::{
+    error::GraphQLError,
+    pagination::{Node, PageRequest},
+};
+use crate::connection;
+use crate::database::bundle::Database;
+use crate::model::{Cursor, CursorPosition, Snowflake};
+use juniper::GraphQLObject;
+use scylla::macros::FromRow;
+
+/// Every title, in scylla's token order.
+const TITLES_QUERY: &str =
+    "SELECT id, name, alt_names, authors, tags, publisher, format, status, cover FROM titles";
+
+#[derive(GraphQLObject, Debug, Clone)]
+pub struct Title {
+    pub id: Snowflake,
+    pub name: String,
+    pub alt_names: Vec<String>,
+    pub authors: Vec<String>,
+    pub tags: Vec<String>,
+    pub publisher: Option<String>,
+    pub format: Option<String>,
+    pub status: Option<String>,
+    /// Url of the cover thumbnail.
+    pub cover: Option<String>,
+}
+
+connection!(TitleConnection, TitleEdge, Title);
+
+#[derive(FromRow)]
+struct TitleRow {
+    id: i64,
+    name: Option<String>,
+    alt_names: Option<Vec<String>>,
+    authors: Option<Vec<String>>,
+    tags: Option<Vec<String>>,
+    publisher: Option<String>,
+    format: Option<String>,
+    status: Option<String>,
+    cover: Option<String>,
+}
+
+impl From<TitleRow> for Title {
+    fn from(row: TitleRow) -> Self {
+        Self {
+            id: Snowflake(row.id as u64),
+            name: row.name.unwrap_or_default(),
+            alt_names: row.alt_names.unwrap_or_default(),
+            authors: row.authors.unwrap_or_default(),
+            tags: row.tags.unwrap_or_default(),
+            publisher: row.publisher,
+            format: row.format,
+            status: row.status,
+            cover: row.cover,
+        }
+    }
+}
+
+impl Node for Title {
+    fn position(&self) -> CursorPosition {
+        CursorPosition::from(&self.id)
+    }
+}
+
+pub async fn titles(
+    database: &Database,
+    first: Option<i32>,
+    after: Option<Cursor>,
+) -> Result<TitleConnection, GraphQLError> {
+    let request = PageRequest::new(TITLES_QUERY, &(), first, after)?;
+    let scylla = &database.scylla;
+    let page = match request.after_snowflake() {
+        // an edge's cursor, resume right after that title in token order.
+        Some(after) => {
+            let query = format!("{} WHERE token(id) > token(?)", TITLES_QUERY);
+            scylla
+                .query_page::<TitleRow>(&query, (after.0 as i64,), request.first, None)
+                .await?
+        }
+        None => {
+            scylla
+                .query_page::<TitleRow>(TITLES_QUERY, (), request.first, request.paging_state())
+                .await?
+        }
+    };
+    Ok(TitleConnection::new(page.map(Title::from), &request))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::database::scylla::ScyllaPage;
+
+    fn title(id: u64) -> Title {
+        Title::from(TitleRow {
+            id: id as i64,
+            name: Some(format!("title #{}", id)),
+            alt_names: None,
+            authors: None,
+            tags: None,
+            publisher: None,
+            format: None,
+            status: None,
+            cover: None,
+        })
+    }
+
+    #[test]
+    fn connection_from_page() {
+        let request = PageRequest::new(TITLES_QUERY, &(), Some(2), None).unwrap();
+        let page = ScyllaPage {
+            rows: vec![title(1), title(2)],
+            paging_state: Some(vec![7, 7]),
+        };
+        let connection = TitleConnection::new(page, &request);
+        assert_eq!(connection.edges.len(), 2);
+        assert_eq!(
+            connection.edges[1].cursor.position,
+            CursorPosition::Snowflake(2)
+        );
+        assert!(connection.page_info.has_next_page);
+        assert!(!connection.page_info.has_previous_page);
+
+        // the end cursor resumes from scylla's paging state.
+        let end = connection.page_info.end_cursor.unwrap();
+        let next = PageRequest::new(TITLES_QUERY, &(), Some(2), Some(end)).unwrap();
+        assert_eq!(next.paging_state(), Some(vec![7, 7]));
+
+        // an edge's cursor resumes right after its title.
+        let edge = connection.edges[0].cursor.clone();
+        let next = PageRequest::new(TITLES_QUERY, &(), Some(2), Some(edge)).unwrap();
+        assert_eq!(next.after_snowflake().unwrap().0, 1);
+    }
+
+    #[test]
+    fn last_page() {
+        let request = PageRequest::new(TITLES_QUERY, &(), None, None).unwrap();
+        let page = ScyllaPage {
+            rows: vec![title(3)],
+            paging_state: None,
+        };
+        let connection = TitleConnection::new(page, &request);
+        assert!(!connection.page_info.has_next_page);
+        assert_eq!(
+            connection.page_info.end_cursor.unwrap().position,
+            CursorPosition::Snowflake(3)
+        );
+    }
+
+    #[test]
+    fn reject_cursor_of_another_query() {
+        let other = PageRequest::new("SELECT id FROM volumes", &(), None, None).unwrap();
+        let cursor = other.cursor(CursorPosition::Snowflake(1));
+        assert!(PageRequest::new(TITLES_QUERY, &(), None, Some(cursor)).is_err());
+    }
+}
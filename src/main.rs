@@ -35,12 +35,30 @@ async fn main() -> std::io::Result<()> {
     .await
     .unwrap();
 
+    let database_clone = database.clone();
+    std::thread::spawn(move || {
+        let rt = tokio::runtime::Runtime::new().unwrap();
+        rt.block_on(database::indexer::run(database_clone));
+    });
+
+    if config.num_job_worker > 0 {
+        let jobs = database::jobs::handlers(database.clone());
+        let num_job_worker = config.num_job_worker;
+        std::thread::spawn(move || {
+            let rt = tokio::runtime::Runtime::new().unwrap();
+            rt.block_on(database::jobs::run(jobs, num_job_worker));
+        });
+    }
+
     log::info!("starting server on port {}", config.http_port);
 
+    let rate_limit = config.rate_limit.clone();
+
     HttpServer::new(move || {
         App::new()
             .app_data(web::Data::new(schema.clone()))
             .app_data(web::Data::new(database.clone()))
+            .app_data(web::Data::new(rate_limit.clone()))
             .configure(graphql::route)
             .wrap(Cors::permissive())
             .wrap(Logger::default())
@@ -0,0 +1,238 @@
+use super::snowflake::Snowflake;
+use crate::graphql::error::{ClientFault, GraphQLError};
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine as _};
+use juniper::{GraphQLScalar, InputValue, ScalarValue, Value};
+use scylla::frame::value::SerializedValues;
+
+/// Tag byte for a cursor that wrap a scylla paging state.
+const PAGING_STATE_TAG: u8 = 0;
+
+/// Tag byte for a cursor that point at a snowflake id.
+const SNOWFLAKE_TAG: u8 = 1;
+
+/// An opaque pagination cursor.
+/// Clients only ever see the encoded form, so the underlying
+///     representation can change without breaking them.
+/// `shape` identifies the query that made it (see `Cursor::shape_of`),
+///     a paging state only makes sense to the query it came from.
+#[derive(GraphQLScalar, Debug, Clone, PartialEq)]
+#[graphql(
+    description = "An opaque cursor used for pagination.",
+    to_output_with = resolve,
+    from_input_with = from_input_value,
+    parse_token(String),
+)]
+pub struct Cursor {
+    pub shape: u32,
+    pub position: CursorPosition,
+}
+
+/// Position of a page inside a (possibly very large) list.
+#[derive(Debug, Clone, PartialEq)]
+pub enum CursorPosition {
+    /// Raw paging state returned by scylla, resume the query where it stopped.
+    PagingState(Vec<u8>),
+
+    /// Resume right after the row with this id,
+    ///     for lists clustered by snowflake.
+    Snowflake(u64),
+}
+
+fn resolve<S: ScalarValue>(v: &Cursor) -> Value<S> {
+    Value::from(v.encode())
+}
+
+fn from_input_value<S: ScalarValue>(v: &InputValue<S>) -> Result<Cursor, GraphQLError> {
+    let cursor_str = match v.as_string_value() {
+        None => return Err(GraphQLError::ClientFault(ClientFault::MustNotEmpty())),
+        Some(cursor_str) => cursor_str,
+    };
+    match Cursor::decode(cursor_str) {
+        None => Err(GraphQLError::ClientFault(ClientFault::InvalidCursor(
+            cursor_str.to_owned(),
+        ))),
+        Some(cursor) => Ok(cursor),
+    }
+}
+
+impl Cursor {
+    /// Fingerprint of a query and the values bound to it (its partition key...),
+    ///     a cursor of one partition must not be resumed in another.
+    /// FNV-1a, so cursors survive a rebuild (unlike with std's hasher).
+    pub fn shape_of(query: &str, values: &SerializedValues) -> u32 {
+        let mut hash = fnv1a(0x811c9dc5, query.as_bytes());
+        for value in values.iter() {
+            // length-prefixed, so ("ab", "c") and ("a", "bc") don't collide.
+            hash = match value {
+                None => fnv1a(hash, &(-1i32).to_be_bytes()),
+                Some(bytes) => fnv1a(fnv1a(hash, &(bytes.len() as i32).to_be_bytes()), bytes),
+            };
+        }
+        hash
+    }
+
+    /// Whether this cursor was made by `query` with these `values`.
+    pub fn is_from(&self, query: &str, values: &SerializedValues) -> bool {
+        self.shape == Self::shape_of(query, values)
+    }
+
+    /// Encode the cursor into an url-safe string.
+    pub fn encode(&self) -> String {
+        let mut bytes: Vec<u8> = vec![];
+        match &self.position {
+            CursorPosition::PagingState(state) => {
+                bytes.push(PAGING_STATE_TAG);
+                bytes.extend_from_slice(&self.shape.to_be_bytes());
+                bytes.extend_from_slice(state);
+            }
+            CursorPosition::Snowflake(id) => {
+                bytes.push(SNOWFLAKE_TAG);
+                bytes.extend_from_slice(&self.shape.to_be_bytes());
+                bytes.extend_from_slice(&id.to_be_bytes());
+            }
+        };
+        URL_SAFE_NO_PAD.encode(bytes)
+    }
+
+    /// Decode a cursor previously made by `encode`,
+    ///     `None` if the input is not one of ours.
+    pub fn decode(cursor: &str) -> Option<Cursor> {
+        let bytes = URL_SAFE_NO_PAD.decode(cursor).ok()?;
+        let (tag, rest) = bytes.split_first()?;
+        if rest.len() < 4 {
+            return None;
+        }
+        let (shape, payload) = rest.split_at(4);
+        let shape = u32::from_be_bytes(shape.try_into().ok()?);
+        let position = match *tag {
+            PAGING_STATE_TAG => CursorPosition::PagingState(payload.to_vec()),
+            SNOWFLAKE_TAG => {
+                let id: [u8; 8] = payload.try_into().ok()?;
+                CursorPosition::Snowflake(u64::from_be_bytes(id))
+            }
+            _ => return None,
+        };
+        Some(Self { shape, position })
+    }
+
+    /// Paging state to hand back to scylla, if this cursor carry one.
+    pub fn paging_state(&self) -> Option<Vec<u8>> {
+        match &self.position {
+            CursorPosition::PagingState(state) => Some(state.clone()),
+            _ => None,
+        }
+    }
+
+    /// Snowflake to be used as the bound of a range query, if this cursor carry one.
+    pub fn snowflake(&self) -> Option<Snowflake> {
+        match &self.position {
+            CursorPosition::Snowflake(id) => Some(Snowflake(*id)),
+            _ => None,
+        }
+    }
+}
+
+fn fnv1a(mut hash: u32, bytes: &[u8]) -> u32 {
+    for byte in bytes {
+        hash ^= *byte as u32;
+        hash = hash.wrapping_mul(0x01000193);
+    }
+    hash
+}
+
+impl From<&Snowflake> for CursorPosition {
+    fn from(snowflake: &Snowflake) -> Self {
+        Self::Snowflake(snowflake.0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const QUERY: &str = "SELECT id FROM titles";
+
+    fn values(values: &[&str]) -> SerializedValues {
+        let mut serialized = SerializedValues::new();
+        for value in values {
+            serialized.add_value(value).unwrap();
+        }
+        serialized
+    }
+
+    fn cursor(position: CursorPosition) -> Cursor {
+        Cursor {
+            shape: Cursor::shape_of(QUERY, &values(&[])),
+            position,
+        }
+    }
+
+    #[test]
+    fn paging_state_round_trip() {
+        let cursor = cursor(CursorPosition::PagingState(vec![0, 1, 2, 254, 255]));
+        assert_eq!(Cursor::decode(&cursor.encode()), Some(cursor));
+    }
+
+    #[test]
+    fn empty_paging_state_round_trip() {
+        let cursor = cursor(CursorPosition::PagingState(vec![]));
+        assert_eq!(Cursor::decode(&cursor.encode()), Some(cursor));
+    }
+
+    #[test]
+    fn snowflake_round_trip() {
+        const SNOWFLAKE_ID: u64 = 277431062064267264;
+        let cursor = cursor(CursorPosition::from(&Snowflake(SNOWFLAKE_ID)));
+        let decoded = Cursor::decode(&cursor.encode()).unwrap();
+        assert_eq!(decoded.snowflake().unwrap().0, SNOWFLAKE_ID);
+        assert_eq!(decoded.paging_state(), None);
+    }
+
+    #[test]
+    fn shape_ties_cursor_to_query() {
+        let cursor = cursor(CursorPosition::PagingState(vec![1, 2, 3]));
+        let decoded = Cursor::decode(&cursor.encode()).unwrap();
+        assert!(decoded.is_from(QUERY, &values(&[])));
+        assert!(!decoded.is_from("SELECT id FROM titles WHERE publisher = ?", &values(&[])));
+    }
+
+    #[test]
+    fn shape_ties_cursor_to_bound_values() {
+        const BY_PUBLISHER: &str = "SELECT id FROM titles_by_publisher WHERE publisher = ?";
+        let shape = Cursor::shape_of(BY_PUBLISHER, &values(&["kodansha"]));
+        assert_eq!(
+            shape,
+            Cursor::shape_of(BY_PUBLISHER, &values(&["kodansha"]))
+        );
+        assert_ne!(
+            shape,
+            Cursor::shape_of(BY_PUBLISHER, &values(&["shueisha"]))
+        );
+        assert_ne!(
+            Cursor::shape_of(BY_PUBLISHER, &values(&["ab", "c"])),
+            Cursor::shape_of(BY_PUBLISHER, &values(&["a", "bc"]))
+        );
+    }
+
+    #[test]
+    fn decode_garbage() {
+        assert_eq!(Cursor::decode(""), None);
+        assert_eq!(Cursor::decode("not a cursor!"), None);
+        assert_eq!(
+            Cursor::decode(&URL_SAFE_NO_PAD.encode([42, 0, 0, 0, 0])),
+            None
+        );
+        assert_eq!(
+            Cursor::decode(&URL_SAFE_NO_PAD.encode([PAGING_STATE_TAG, 0])),
+            None
+        );
+    }
+
+    #[test]
+    fn decode_truncated_snowflake() {
+        assert_eq!(
+            Cursor::decode(&URL_SAFE_NO_PAD.encode([SNOWFLAKE_TAG, 0, 0, 0, 0, 0, 0, 0])),
+            None
+        );
+    }
+}
@@ -1,3 +1,5 @@
+pub mod cursor;
 pub mod snowflake;
 
+pub use cursor::*;
 pub use snowflake::*;
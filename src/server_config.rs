@@ -1,8 +1,9 @@
-use crate::server_config::database::DatabaseConfig;
+use crate::server_config::{database::DatabaseConfig, rate_limit::RateLimitConfig};
 use std::{env, result::Result, str::FromStr};
 
 pub mod database;
 pub mod manticore;
+pub mod rate_limit;
 pub mod redis;
 pub mod scylla;
 
@@ -10,13 +11,17 @@ const RUST_ENV: &str = "RUST_ENV";
 const RUST_LOG: &str = "RUST_LOG";
 const HTTP_PORT: &str = "HTTP_PORT";
 const NUM_WORKER: &str = "NUM_WORKER";
+const NUM_JOB_WORKER: &str = "NUM_JOB_WORKER";
 
 #[derive(Clone)]
 pub struct ServerConfig {
     pub is_production: bool,
     pub http_port: u16,
     pub num_worker: usize,
+    /// Background job workers (see `database::jobs`), 0 to run none on this instance.
+    pub num_job_worker: usize,
     pub database: DatabaseConfig,
+    pub rate_limit: RateLimitConfig,
 }
 
 #[derive(Clone, Debug)]
@@ -24,6 +29,7 @@ pub enum EnvParseError {
     KeyNotFound(String),
     KeyIsEmpty(String),
     InvalidNumber(String, String),
+    InvalidValue(String, String),
 }
 
 impl ServerConfig {
@@ -47,6 +53,18 @@ impl ServerConfig {
         }
     }
 
+    /// `default` if the key isn't set (or empty), but an error if it's not a number.
+    pub fn get_num_or<T: FromStr>(key: &str, default: T) -> Result<T, EnvParseError> {
+        match Self::get_str(key) {
+            Err(_) => Ok(default),
+            Ok(val) if val == "" => Ok(default),
+            Ok(val) => match val.parse::<T>() {
+                Ok(num) => Ok(num),
+                Err(_) => Err(EnvParseError::InvalidValue(key.to_string(), val)),
+            },
+        }
+    }
+
     pub fn load() -> Result<Self, EnvParseError> {
         dotenv::dotenv().ok();
 
@@ -58,7 +76,9 @@ impl ServerConfig {
             is_production,
             http_port: Self::get_num::<u16>(&HTTP_PORT).unwrap_or(8080),
             num_worker: Self::get_num::<usize>(&NUM_WORKER).unwrap_or(2),
+            num_job_worker: Self::get_num::<usize>(&NUM_JOB_WORKER).unwrap_or(2),
             database: DatabaseConfig::load()?,
+            rate_limit: RateLimitConfig::load()?,
         })
     }
 }
@@ -6,6 +6,23 @@ const MANTICORE_PORT: &str = "MANTICORE_PORT";
 const MANTICORE_USER: &str = "MANTICORE_USER";
 const MANTICORE_PASSWORD: &str = "MANTICORE_PASSWORD";
 const MANTICORE_PREFIX: &str = "MANTICORE_PREFIX";
+const MANTICORE_WORDFORMS: &str = "MANTICORE_WORDFORMS";
+const MANTICORE_POOL_MAX: &str = "MANTICORE_POOL_MAX";
+const MANTICORE_CONNECT_TIMEOUT: &str = "MANTICORE_CONNECT_TIMEOUT";
+const MANTICORE_IDLE_TIMEOUT: &str = "MANTICORE_IDLE_TIMEOUT";
+const MANTICORE_REQUEST_TIMEOUT: &str = "MANTICORE_REQUEST_TIMEOUT";
+const MANTICORE_TRANSPORT: &str = "MANTICORE_TRANSPORT";
+const MANTICORE_HTTP_URI: &str = "MANTICORE_HTTP_URI";
+
+/// How search and indexing requests reach manticore,
+///     schema synchronization always goes through SQL.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ManticoreTransport {
+    /// SphinxQL over the mysql protocol.
+    Sql,
+    /// The JSON api (`/search`, `/insert`, `/bulk`...).
+    Http,
+}
 
 #[derive(Clone)]
 pub struct ManticoreConfig {
@@ -14,16 +31,75 @@ pub struct ManticoreConfig {
     pub user: String,
     pub password: String,
     pub prefix: String,
+    /// Path (on the manticore server) of the wordforms file,
+    ///     used for synonyms and alternative titles.
+    pub wordforms: Option<String>,
+    /// Maximum number of connections kept in the pool.
+    pub pool_max: u32,
+    /// Timeout when opening (or waiting for) a connection, in miliseconds.
+    pub connect_timeout: u64,
+    /// Idle connections are closed after this long, in miliseconds, 0 to keep them forever.
+    pub idle_timeout: u64,
+    /// Timeout of a whole request to the JSON api, in miliseconds.
+    pub request_timeout: u64,
+    pub transport: ManticoreTransport,
+    /// Base url of the JSON api, e.g. `http://127.0.0.1:9308`.
+    pub http_uri: String,
 }
 
 impl ManticoreConfig {
     pub fn load() -> Result<ManticoreConfig, EnvParseError> {
+        let prefix = ServerConfig::get_str(MANTICORE_PREFIX).unwrap_or("".to_string());
+        if !prefix
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '_')
+        {
+            return Err(EnvParseError::InvalidValue(
+                MANTICORE_PREFIX.to_string(),
+                prefix,
+            ));
+        }
+        let pool_max = ServerConfig::get_num_or::<u32>(MANTICORE_POOL_MAX, 5)?;
+        if pool_max == 0 {
+            return Err(EnvParseError::InvalidValue(
+                MANTICORE_POOL_MAX.to_string(),
+                pool_max.to_string(),
+            ));
+        }
+        let transport = match ServerConfig::get_str(MANTICORE_TRANSPORT)
+            .unwrap_or("sql".to_string())
+            .to_lowercase()
+            .as_str()
+        {
+            "" | "sql" => ManticoreTransport::Sql,
+            "http" => ManticoreTransport::Http,
+            other => {
+                return Err(EnvParseError::InvalidValue(
+                    MANTICORE_TRANSPORT.to_string(),
+                    other.to_string(),
+                ))
+            }
+        };
+        let uri = ServerConfig::get_str(MANTICORE_URI)?;
+        let http_uri = ServerConfig::get_str(MANTICORE_HTTP_URI)
+            .ok()
+            .filter(|http_uri| http_uri != "")
+            .unwrap_or(format!("http://{}:9308", uri));
         Ok(Self {
-            uri: ServerConfig::get_str(MANTICORE_URI)?,
+            uri,
             port: ServerConfig::get_num::<u16>(MANTICORE_PORT)?,
             user: ServerConfig::get_str(MANTICORE_USER).unwrap_or("".to_string()),
             password: ServerConfig::get_str(MANTICORE_PASSWORD).unwrap_or("".to_string()),
-            prefix: ServerConfig::get_str(MANTICORE_PREFIX).unwrap_or("".to_string()),
+            prefix,
+            wordforms: ServerConfig::get_str(MANTICORE_WORDFORMS)
+                .ok()
+                .filter(|path| path != ""),
+            pool_max,
+            connect_timeout: ServerConfig::get_num_or(MANTICORE_CONNECT_TIMEOUT, 5000)?,
+            idle_timeout: ServerConfig::get_num_or(MANTICORE_IDLE_TIMEOUT, 10 * 60 * 1000)?,
+            request_timeout: ServerConfig::get_num_or(MANTICORE_REQUEST_TIMEOUT, 10 * 1000)?,
+            transport,
+            http_uri: http_uri.trim_end_matches('/').to_string(),
         })
     }
 
@@ -0,0 +1,39 @@
+use super::{EnvParseError, ServerConfig};
+use std::result::Result;
+
+const RATE_LIMIT_WINDOW: &str = "RATE_LIMIT_WINDOW";
+const RATE_LIMIT_QUERIES: &str = "RATE_LIMIT_QUERIES";
+const RATE_LIMIT_MUTATIONS: &str = "RATE_LIMIT_MUTATIONS";
+const RATE_LIMIT_TRUSTED_PROXIES: &str = "RATE_LIMIT_TRUSTED_PROXIES";
+
+#[derive(Clone)]
+pub struct RateLimitConfig {
+    /// Length of the window the budgets below are given for, in miliseconds.
+    pub window: u64,
+    /// Queries a client can make per window, 0 for no limit.
+    pub queries: u64,
+    /// Mutations a client can make per window, 0 for no limit.
+    pub mutations: u64,
+    /// Number of reverse proxies in front of the api, each appending to
+    ///     `X-Forwarded-For`. The client is the hop the outermost one added,
+    ///     0 to ignore the header and use the peer address.
+    pub trusted_proxies: usize,
+}
+
+impl RateLimitConfig {
+    pub fn load() -> Result<Self, EnvParseError> {
+        let window = ServerConfig::get_num_or::<u64>(RATE_LIMIT_WINDOW, 60 * 1000)?;
+        if window == 0 {
+            return Err(EnvParseError::InvalidValue(
+                RATE_LIMIT_WINDOW.to_string(),
+                window.to_string(),
+            ));
+        }
+        Ok(Self {
+            window,
+            queries: ServerConfig::get_num_or(RATE_LIMIT_QUERIES, 300)?,
+            mutations: ServerConfig::get_num_or(RATE_LIMIT_MUTATIONS, 30)?,
+            trusted_proxies: ServerConfig::get_num_or(RATE_LIMIT_TRUSTED_PROXIES, 0)?,
+        })
+    }
+}
@@ -2,16 +2,164 @@ use super::{EnvParseError, ServerConfig};
 use std::result::Result;
 
 const REDIS_URI: &str = "REDIS_URI";
+const REDIS_MODE: &str = "REDIS_MODE";
+const REDIS_SENTINEL_MASTER: &str = "REDIS_SENTINEL_MASTER";
+const REDIS_USERNAME: &str = "REDIS_USERNAME";
+const REDIS_PASSWORD: &str = "REDIS_PASSWORD";
+const REDIS_POOL_SIZE: &str = "REDIS_POOL_SIZE";
+const REDIS_CONNECT_TIMEOUT: &str = "REDIS_CONNECT_TIMEOUT";
+const REDIS_RESPONSE_TIMEOUT: &str = "REDIS_RESPONSE_TIMEOUT";
+const REDIS_LOCAL_CAPACITY: &str = "REDIS_LOCAL_CAPACITY";
+const REDIS_LOCAL_TTL: &str = "REDIS_LOCAL_TTL";
+const REDIS_CACHE_FORMAT: &str = "REDIS_CACHE_FORMAT";
+const REDIS_CACHE_COMPRESSION: &str = "REDIS_CACHE_COMPRESSION";
+const REDIS_CACHE_COMPRESSION_THRESHOLD: &str = "REDIS_CACHE_COMPRESSION_THRESHOLD";
+
+/// How redis is deployed.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum RedisMode {
+    /// A single server.
+    Standalone,
+    /// The master is discovered through sentinels, and again after a failover.
+    Sentinel,
+    /// Commands are routed to the node holding their slot.
+    Cluster,
+}
+
+/// How cached values are serialized.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum CacheFormat {
+    Cbor,
+    MessagePack,
+    /// The most compact, but only for types that don't need `deserialize_any`
+    ///     (e.g. no `#[serde(untagged)]`, `#[serde(flatten)]`).
+    Bincode,
+}
+
+/// How large cached values are compressed.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum CacheCompression {
+    None,
+    Zstd,
+    Lz4,
+}
 
 #[derive(Clone)]
 pub struct RedisConfig {
-    pub uri: String,
+    pub mode: RedisMode,
+    /// The server, the sentinels or the cluster nodes (only a few are needed,
+    ///     the others are discovered), `rediss://` for TLS.
+    /// With sentinel, the master is reached with the scheme and database of these.
+    pub uris: Vec<String>,
+    /// Name of the master monitored by the sentinels.
+    pub sentinel_master: String,
+    /// ACL credentials of the data nodes, override the ones in `uris`
+    ///     (sentinels only use the ones in their uri).
+    pub username: Option<String>,
+    pub password: Option<String>,
+    /// Number of connections opened, each one is shared by concurrent commands.
+    pub pool_size: usize,
+    /// Timeout when opening a connection, in miliseconds.
+    pub connect_timeout: u64,
+    /// Timeout when waiting for the reply to a command, in miliseconds.
+    pub response_timeout: u64,
+    /// Maximum number of entries kept in memory in front of redis, 0 to disable.
+    pub local_capacity: u64,
+    /// How long an entry is kept in memory, in miliseconds.
+    pub local_ttl: u64,
+    pub cache_format: CacheFormat,
+    pub cache_compression: CacheCompression,
+    /// Values smaller than this (in bytes) are never compressed.
+    pub cache_compression_threshold: usize,
 }
 
 impl RedisConfig {
     pub fn load() -> Result<RedisConfig, EnvParseError> {
+        let pool_size = ServerConfig::get_num_or::<usize>(REDIS_POOL_SIZE, 2)?;
+        if pool_size == 0 {
+            return Err(EnvParseError::InvalidValue(
+                REDIS_POOL_SIZE.to_string(),
+                pool_size.to_string(),
+            ));
+        }
+        let mode = match ServerConfig::get_str(REDIS_MODE)
+            .unwrap_or("standalone".to_string())
+            .to_lowercase()
+            .as_str()
+        {
+            "" | "standalone" => RedisMode::Standalone,
+            "sentinel" => RedisMode::Sentinel,
+            "cluster" => RedisMode::Cluster,
+            other => {
+                return Err(EnvParseError::InvalidValue(
+                    REDIS_MODE.to_string(),
+                    other.to_string(),
+                ))
+            }
+        };
+        let uri = ServerConfig::get_str(REDIS_URI)?;
+        let uris: Vec<String> = uri
+            .split(',')
+            .map(|uri| uri.trim().to_string())
+            .filter(|uri| uri != "")
+            .collect();
+        if uris.is_empty() || (mode == RedisMode::Standalone && uris.len() > 1) {
+            return Err(EnvParseError::InvalidValue(REDIS_URI.to_string(), uri));
+        }
+        let cache_format = match ServerConfig::get_str(REDIS_CACHE_FORMAT)
+            .unwrap_or("cbor".to_string())
+            .to_lowercase()
+            .as_str()
+        {
+            "" | "cbor" => CacheFormat::Cbor,
+            "msgpack" | "messagepack" => CacheFormat::MessagePack,
+            "bincode" => CacheFormat::Bincode,
+            other => {
+                return Err(EnvParseError::InvalidValue(
+                    REDIS_CACHE_FORMAT.to_string(),
+                    other.to_string(),
+                ))
+            }
+        };
+        let cache_compression = match ServerConfig::get_str(REDIS_CACHE_COMPRESSION)
+            .unwrap_or("none".to_string())
+            .to_lowercase()
+            .as_str()
+        {
+            "" | "none" => CacheCompression::None,
+            "zstd" => CacheCompression::Zstd,
+            "lz4" => CacheCompression::Lz4,
+            other => {
+                return Err(EnvParseError::InvalidValue(
+                    REDIS_CACHE_COMPRESSION.to_string(),
+                    other.to_string(),
+                ))
+            }
+        };
         Ok(Self {
-            uri: ServerConfig::get_str(REDIS_URI)?,
+            mode,
+            uris,
+            sentinel_master: ServerConfig::get_str(REDIS_SENTINEL_MASTER)
+                .ok()
+                .filter(|name| name != "")
+                .unwrap_or("mymaster".to_string()),
+            username: ServerConfig::get_str(REDIS_USERNAME)
+                .ok()
+                .filter(|username| username != ""),
+            password: ServerConfig::get_str(REDIS_PASSWORD)
+                .ok()
+                .filter(|password| password != ""),
+            pool_size,
+            connect_timeout: ServerConfig::get_num_or(REDIS_CONNECT_TIMEOUT, 5000)?,
+            response_timeout: ServerConfig::get_num_or(REDIS_RESPONSE_TIMEOUT, 2000)?,
+            local_capacity: ServerConfig::get_num_or(REDIS_LOCAL_CAPACITY, 0)?,
+            local_ttl: ServerConfig::get_num_or(REDIS_LOCAL_TTL, 5000)?,
+            cache_format,
+            cache_compression,
+            cache_compression_threshold: ServerConfig::get_num_or(
+                REDIS_CACHE_COMPRESSION_THRESHOLD,
+                1024,
+            )?,
         })
     }
 }
@@ -5,6 +5,12 @@ const SCYLLA_URI: &str = "SCYLLA_URI";
 const SCYLLA_USER: &str = "SCYLLA_USER";
 const SCYLLA_PASSWORD: &str = "SCYLLA_PASSWORD";
 const SCYLLA_KEYSPACE: &str = "SCYLLA_KEYSPACE";
+const SCYLLA_CONNECT_TIMEOUT: &str = "SCYLLA_CONNECT_TIMEOUT";
+const SCYLLA_REQUEST_TIMEOUT: &str = "SCYLLA_REQUEST_TIMEOUT";
+const SCYLLA_RETRY_MAX: &str = "SCYLLA_RETRY_MAX";
+const SCYLLA_RETRY_BACKOFF: &str = "SCYLLA_RETRY_BACKOFF";
+const SCYLLA_SPECULATIVE_MAX: &str = "SCYLLA_SPECULATIVE_MAX";
+const SCYLLA_SPECULATIVE_DELAY: &str = "SCYLLA_SPECULATIVE_DELAY";
 
 #[derive(Clone)]
 pub struct ScyllaConfig {
@@ -12,6 +18,18 @@ pub struct ScyllaConfig {
     pub user: String,
     pub password: String,
     pub keyspace: String,
+    /// Timeout when opening a connection to a node, in miliseconds.
+    pub connect_timeout: u64,
+    /// Timeout of a single request, in miliseconds.
+    pub request_timeout: u64,
+    /// How many times an idempotent query is retried on transient errors.
+    pub retry_max: u32,
+    /// Base delay between two retries, doubled after each attempt, in miliseconds.
+    pub retry_backoff: u64,
+    /// Maximum number of speculative executions of an idempotent query, 0 to disable.
+    pub speculative_max: usize,
+    /// Delay before starting the next speculative execution, in miliseconds.
+    pub speculative_delay: u64,
 }
 
 impl ScyllaConfig {
@@ -25,6 +43,12 @@ impl ScyllaConfig {
             user: ServerConfig::get_str(SCYLLA_USER).unwrap_or("".to_string()),
             password: ServerConfig::get_str(SCYLLA_PASSWORD).unwrap_or("".to_string()),
             keyspace,
+            connect_timeout: ServerConfig::get_num_or(SCYLLA_CONNECT_TIMEOUT, 5000)?,
+            request_timeout: ServerConfig::get_num_or(SCYLLA_REQUEST_TIMEOUT, 10000)?,
+            retry_max: ServerConfig::get_num_or(SCYLLA_RETRY_MAX, 3)?,
+            retry_backoff: ServerConfig::get_num_or(SCYLLA_RETRY_BACKOFF, 100)?,
+            speculative_max: ServerConfig::get_num_or(SCYLLA_SPECULATIVE_MAX, 2)?,
+            speculative_delay: ServerConfig::get_num_or(SCYLLA_SPECULATIVE_DELAY, 100)?,
         })
     }
 